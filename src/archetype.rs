@@ -0,0 +1,222 @@
+use anyhow::{Result, anyhow};
+use chrono::Local;
+use minijinja::{Environment, context, value::Value};
+use std::{
+    fs,
+    io::Write,
+    path::{Component, Path},
+};
+
+use crate::{config::Config, filters::slugify, i18n::split_language};
+
+/// Archetype used when the project doesn't define one.
+const DEFAULT_ARCHETYPE: &str = "---
+title: \"{{ title }}\"
+date: {{ date }}
+draft: true
+---
+";
+
+/// Find the archetype for a content path, relative to the content directory.
+///
+/// This is `<archetype_dir>/<section>.md` for content inside a section, then
+/// `<archetype_dir>/default.md`, then the built-in archetype.
+fn find_archetype(config: &Config, rel_path: &Path) -> Result<String> {
+    let archetype_root = config.project_path(&config.build.archetype_dir);
+    let mut candidates = Vec::new();
+
+    if let Some(Component::Normal(section)) = rel_path.components().next()
+        && rel_path.components().count() > 1
+    {
+        let mut file_name = section.to_os_string();
+        file_name.push(".md");
+        candidates.push(archetype_root.join(file_name));
+    }
+    candidates.push(archetype_root.join("default.md"));
+
+    for candidate in candidates {
+        if candidate.is_file() {
+            println!("Using archetype {}", candidate.display());
+            return Ok(fs::read_to_string(candidate)?);
+        }
+    }
+
+    Ok(DEFAULT_ARCHETYPE.to_string())
+}
+
+/// Convert a file stem such as `my-first_post` into a title, `My First Post`.
+fn title_from_stem(stem: &str) -> String {
+    stem.split(['-', '_', ' '])
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Create a new content file from an archetype.
+///
+/// `path` is relative to the content directory, with `.md` appended if it
+/// doesn't end with it. Existing files are never overwritten.
+pub fn new_content(config: &Config, path: &str) -> Result<()> {
+    let path = match path.ends_with(".md") {
+        true => path.to_string(),
+        false => format!("{path}.md"),
+    };
+    let path = path.as_str();
+    let rel_path = Path::new(path);
+
+    if rel_path.is_absolute()
+        || rel_path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "'{}' must be a path inside the content directory",
+            path
+        ));
+    }
+
+    let dst_path = config
+        .project_path(&config.build.content_dir)
+        .join(rel_path);
+
+    if dst_path.exists() {
+        return Err(anyhow!("'{}' already exists", dst_path.display()));
+    }
+
+    // Ignore any language suffix when deriving the title, and use the
+    // directory name for section index pages.
+    let (_, key) = split_language(config, rel_path);
+    let stem = match key.file_stem().and_then(|s| s.to_str()) {
+        Some("index") => key
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|s| s.to_str())
+            .unwrap_or("index"),
+        Some(stem) => stem,
+        None => return Err(anyhow!("'{}' is not a valid file name", path)),
+    };
+
+    let archetype = find_archetype(config, rel_path)?;
+
+    let mut env = Environment::new();
+    env.set_keep_trailing_newline(true);
+    let rendered = env.render_str(
+        &archetype,
+        context! {
+            title => title_from_stem(stem),
+            date => Local::now().date_naive().to_string(),
+            slug => slugify(stem),
+            path => path,
+            site => Value::from_serialize(&config.site),
+            extra => Value::from_serialize(&config.extra),
+        },
+    )?;
+
+    if let Some(parent) = dst_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // `create_new` guards against the file appearing since the check above.
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&dst_path)?;
+    file.write_all(rendered.as_bytes())?;
+
+    println!("Created {}", dst_path.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LoadOptions;
+    use tempfile::tempdir;
+
+    fn load_config(dir: &Path) -> Config {
+        let config_path = dir.join("tars.toml");
+        fs::write(&config_path, "[site]\ntitle = \"Test\"\n").unwrap();
+        Config::from_file(config_path.to_str().unwrap(), &LoadOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_new_content_archetypes() {
+        let dir = tempdir().unwrap();
+        let config = load_config(dir.path());
+        let content = dir.path().join("content");
+
+        new_content(&config, "my-first_post").unwrap();
+        let text = fs::read_to_string(content.join("my-first_post.md")).unwrap();
+        assert!(
+            text.starts_with("---\ntitle: \"My First Post\"\n"),
+            "{text}"
+        );
+        assert!(text.ends_with("draft: true\n---\n"), "{text}");
+
+        fs::create_dir(dir.path().join("archetypes")).unwrap();
+        fs::write(
+            dir.path().join("archetypes/default.md"),
+            "{{ title }} {{ slug }}\n",
+        )
+        .unwrap();
+        new_content(&config, "docs/getting-started/index").unwrap();
+        assert_eq!(
+            fs::read_to_string(content.join("docs/getting-started/index.md")).unwrap(),
+            "Getting Started getting-started\n"
+        );
+    }
+
+    #[test]
+    fn test_new_content_errors() {
+        let dir = tempdir().unwrap();
+        let config = load_config(dir.path());
+
+        for path in ["../outside", "docs/../../outside", "/tmp/outside"] {
+            let err = new_content(&config, path).unwrap_err();
+            assert!(
+                err.to_string()
+                    .ends_with("must be a path inside the content directory"),
+                "{err}"
+            );
+        }
+        assert!(!dir.path().join("outside.md").exists());
+
+        let existing = dir.path().join("content/post.md");
+        fs::create_dir(dir.path().join("content")).unwrap();
+        fs::write(&existing, "keep").unwrap();
+        let err = new_content(&config, "post").unwrap_err();
+        assert!(err.to_string().ends_with("already exists"), "{err}");
+        assert_eq!(fs::read_to_string(existing).unwrap(), "keep");
+    }
+
+    #[test]
+    fn test_new_content_in_dotted_section() {
+        let dir = tempdir().unwrap();
+        let config = load_config(dir.path());
+        fs::create_dir(dir.path().join("archetypes")).unwrap();
+        fs::write(dir.path().join("archetypes/v1.2.md"), "v1.2 {{ title }}\n").unwrap();
+        fs::write(dir.path().join("archetypes/v1.md"), "v1 {{ title }}\n").unwrap();
+
+        new_content(&config, "v1.2/getting-started").unwrap();
+        new_content(&config, "v1.2/faq.md").unwrap();
+
+        let content = dir.path().join("content/v1.2");
+        assert_eq!(
+            fs::read_to_string(content.join("getting-started.md")).unwrap(),
+            "v1.2 Getting Started\n"
+        );
+        assert_eq!(
+            fs::read_to_string(content.join("faq.md")).unwrap(),
+            "v1.2 Faq\n"
+        );
+        assert!(new_content(&config, "v1.2/faq").is_err());
+    }
+}
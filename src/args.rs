@@ -1,9 +1,11 @@
 use anyhow::{Result, anyhow};
 use clap::{
-    Arg, ArgAction, Command,
+    Arg, ArgAction, ArgMatches, Command,
     builder::styling::{AnsiColor, Effects, Styles},
 };
 
+use crate::{config::parse_override, init::DEFAULT_STARTER};
+
 pub const DEFAULT_TARS_CONFIG_FILE: &str = "tars.toml";
 
 pub struct Args {
@@ -12,28 +14,74 @@ pub struct Args {
 
 pub enum TarsSubcommand {
     Init(InitArgs),
+    New(NewArgs),
     Build(BuildArgs),
     Clean(CleanArgs),
     Serve(ServeArgs),
     Plugin(PluginArgs),
+    Config(ConfigArgs),
 }
 
 pub struct InitArgs {
     pub force: bool,
     pub path: String,
+    pub starter: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub base_url: Option<String>,
+}
+
+pub struct NewArgs {
+    pub config: String,
+    pub root: Option<String>,
+    pub path: String,
 }
 
 pub struct BuildArgs {
     pub config: String,
+    pub root: Option<String>,
+    pub env: Option<String>,
+    pub overrides: OverrideArgs,
     pub no_verify: bool,
+    pub force: bool,
 }
 
 pub struct CleanArgs {
     pub config: String,
+    pub root: Option<String>,
+    pub env: Option<String>,
 }
 
 pub struct ServeArgs {
     pub config: String,
+    pub root: Option<String>,
+    pub env: Option<String>,
+    pub overrides: OverrideArgs,
+}
+
+/// Configuration values overridden on the command line.
+#[derive(Default)]
+pub struct OverrideArgs {
+    pub set: Vec<String>,
+    pub base_url: Option<String>,
+    pub output: Option<String>,
+    pub drafts: bool,
+    pub jobs: Option<usize>,
+}
+
+pub struct ConfigArgs {
+    pub subcommand: ConfigSubcommand,
+}
+
+pub enum ConfigSubcommand {
+    Show(ConfigShowArgs),
+}
+
+pub struct ConfigShowArgs {
+    pub config: String,
+    pub root: Option<String>,
+    pub env: Option<String>,
+    pub overrides: OverrideArgs,
 }
 
 pub struct PluginArgs {
@@ -48,10 +96,12 @@ pub enum PluginSubcommand {
 
 pub struct PluginListArgs {
     pub config: String,
+    pub root: Option<String>,
 }
 
 pub struct PluginVerifyArgs {
     pub config: String,
+    pub root: Option<String>,
 }
 
 pub struct PluginHashArgs {
@@ -64,6 +114,10 @@ impl Default for InitArgs {
         Self {
             force: false,
             path: ".".to_string(),
+            starter: DEFAULT_STARTER.to_string(),
+            title: None,
+            author: None,
+            base_url: None,
         }
     }
 }
@@ -72,7 +126,11 @@ impl Default for BuildArgs {
     fn default() -> Self {
         Self {
             config: DEFAULT_TARS_CONFIG_FILE.to_string(),
+            root: None,
+            env: None,
+            overrides: OverrideArgs::default(),
             no_verify: false,
+            force: false,
         }
     }
 }
@@ -81,6 +139,8 @@ impl Default for CleanArgs {
     fn default() -> Self {
         Self {
             config: DEFAULT_TARS_CONFIG_FILE.to_string(),
+            root: None,
+            env: None,
         }
     }
 }
@@ -89,6 +149,9 @@ impl Default for ServeArgs {
     fn default() -> Self {
         Self {
             config: DEFAULT_TARS_CONFIG_FILE.to_string(),
+            root: None,
+            env: None,
+            overrides: OverrideArgs::default(),
         }
     }
 }
@@ -97,6 +160,7 @@ impl Default for PluginListArgs {
     fn default() -> Self {
         Self {
             config: DEFAULT_TARS_CONFIG_FILE.to_string(),
+            root: None,
         }
     }
 }
@@ -105,10 +169,92 @@ impl Default for PluginVerifyArgs {
     fn default() -> Self {
         Self {
             config: DEFAULT_TARS_CONFIG_FILE.to_string(),
+            root: None,
         }
     }
 }
 
+impl OverrideArgs {
+    fn from_matches(args: &ArgMatches) -> Self {
+        Self {
+            set: args
+                .get_many::<String>("set")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default(),
+            base_url: args.get_one::<String>("base_url").cloned(),
+            output: args.get_one::<String>("output").cloned(),
+            drafts: args.get_flag("drafts"),
+            jobs: args.get_one::<usize>("jobs").copied(),
+        }
+    }
+
+    /// Returns the overrides as dotted keys and values, in the order applied.
+    pub fn to_overrides(&self) -> Result<Vec<(String, toml::Value)>> {
+        let mut overrides = Vec::new();
+
+        if let Some(base_url) = &self.base_url {
+            overrides.push((
+                "site.base_url".to_string(),
+                toml::Value::String(base_url.clone()),
+            ));
+        }
+        if let Some(output) = &self.output {
+            // Relative to the working directory, rather than the project root.
+            let output = std::env::current_dir()?.join(output);
+            overrides.push((
+                "build.build_dir".to_string(),
+                toml::Value::String(output.to_string_lossy().into_owned()),
+            ));
+        }
+        if self.drafts {
+            overrides.push((
+                "build.include_drafts".to_string(),
+                toml::Value::Boolean(true),
+            ));
+        }
+        if let Some(jobs) = self.jobs {
+            overrides.push(("build.jobs".to_string(), toml::Value::Integer(jobs as i64)));
+        }
+
+        for arg in &self.set {
+            overrides.push(parse_override(arg)?);
+        }
+
+        Ok(overrides)
+    }
+}
+
+fn override_args() -> [Arg; 5] {
+    [
+        Arg::new("set")
+            .long("set")
+            .value_name("KEY=VALUE")
+            .action(ArgAction::Append)
+            .help("Override a configuration value, e.g. build.include_drafts=true"),
+        Arg::new("base_url")
+            .long("base-url")
+            .value_name("URL")
+            .required(false)
+            .help("Override the site base URL"),
+        Arg::new("output")
+            .long("output")
+            .value_name("DIR")
+            .required(false)
+            .help("Override the build output directory"),
+        Arg::new("drafts")
+            .long("drafts")
+            .action(ArgAction::SetTrue)
+            .help("Include draft pages"),
+        Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .required(false)
+            .help("Number of threads used to render pages, defaults to one per CPU"),
+    ]
+}
+
 fn styles() -> Styles {
     Styles::styled()
         .header(AnsiColor::BrightGreen.on_default().effects(Effects::BOLD))
@@ -139,9 +285,58 @@ fn build_cli() -> Command {
                         .default_value(".")
                         .required(false)
                         .help("Create a project in the directory specified by PATH"),
+                    Arg::new("starter")
+                        .short('s')
+                        .long("starter")
+                        .value_name("STARTER")
+                        .default_value(DEFAULT_STARTER)
+                        .required(false)
+                        .help(
+                            "Starter to use: default, blog, docs, portfolio, or a local directory",
+                        ),
+                    Arg::new("title")
+                        .long("title")
+                        .value_name("TITLE")
+                        .required(false)
+                        .help("Site title to use in the starter"),
+                    Arg::new("author")
+                        .long("author")
+                        .value_name("AUTHOR")
+                        .required(false)
+                        .help("Site author to use in the starter"),
+                    Arg::new("base_url")
+                        .long("base-url")
+                        .value_name("URL")
+                        .required(false)
+                        .help("Base URL to use in the starter"),
                 ])
                 .about("Initialize a new project"),
         )
+        .subcommand(
+            Command::new("new")
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .value_name("PATH")
+                        .help("Path of the new content file, relative to the content directory. The .md extension is added if missing"),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("CONFIG")
+                        .required(false)
+                        .default_value(DEFAULT_TARS_CONFIG_FILE)
+                        .help("Specify the configuration file to use"),
+                )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .value_name("DIR")
+                        .required(false)
+                        .help("Resolve project paths relative to DIR instead of the configuration file"),
+                )
+                .about("Create a new content file from an archetype"),
+        )
         .subcommand(
             Command::new("build")
                 .arg(
@@ -152,12 +347,34 @@ fn build_cli() -> Command {
                         .default_value(DEFAULT_TARS_CONFIG_FILE)
                         .help("Specify the configuration file to use"),
                 )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .value_name("DIR")
+                        .required(false)
+                        .help("Resolve project paths relative to DIR instead of the configuration file"),
+                )
                 .arg(
                     Arg::new("no_verify")
                         .long("no-verify")
                         .action(ArgAction::SetTrue)
                         .help("Skip hash verification of plugins"),
                 )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Rebuild every page, ignoring the build cache"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .long("env")
+                        .value_name("ENV")
+                        .required(false)
+                        .help("Apply configuration overrides for the environment ENV"),
+                )
+                .args(override_args())
                 .about("Build the project in the current directory"),
         )
         .subcommand(
@@ -170,6 +387,20 @@ fn build_cli() -> Command {
                         .default_value(DEFAULT_TARS_CONFIG_FILE)
                         .help("Specify the configuration file to use"),
                 )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .value_name("DIR")
+                        .required(false)
+                        .help("Resolve project paths relative to DIR instead of the configuration file"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .long("env")
+                        .value_name("ENV")
+                        .required(false)
+                        .help("Apply configuration overrides for the environment ENV"),
+                )
                 .about("Clean build outputs"),
         )
         .subcommand(
@@ -182,8 +413,55 @@ fn build_cli() -> Command {
                         .default_value(DEFAULT_TARS_CONFIG_FILE)
                         .help("Specify the configuration file to use"),
                 )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .value_name("DIR")
+                        .required(false)
+                        .help("Resolve project paths relative to DIR instead of the configuration file"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .long("env")
+                        .value_name("ENV")
+                        .required(false)
+                        .help("Apply configuration overrides for the environment ENV"),
+                )
+                .args(override_args())
                 .about("Serve generated files"),
         )
+        .subcommand(
+            Command::new("config")
+                .styles(styles())
+                .subcommand(
+                    Command::new("show")
+                        .arg(
+                            Arg::new("config")
+                                .long("config")
+                                .value_name("CONFIG")
+                                .required(false)
+                                .default_value(DEFAULT_TARS_CONFIG_FILE)
+                                .help("Specify the configuration file to use"),
+                        )
+                        .arg(
+                            Arg::new("root")
+                                .long("root")
+                                .value_name("DIR")
+                                .required(false)
+                                .help("Resolve project paths relative to DIR instead of the configuration file"),
+                        )
+                        .arg(
+                            Arg::new("env")
+                                .long("env")
+                                .value_name("ENV")
+                                .required(false)
+                                .help("Apply configuration overrides for the environment ENV"),
+                        )
+                        .args(override_args())
+                        .about("Show the effective configuration"),
+                )
+                .about("Inspect Tars configuration"),
+        )
         .subcommand(
             Command::new("plugin")
                 .styles(styles())
@@ -197,6 +475,13 @@ fn build_cli() -> Command {
                                 .default_value(DEFAULT_TARS_CONFIG_FILE)
                                 .help("Specify the configuration file to use"),
                         )
+                        .arg(
+                            Arg::new("root")
+                                .long("root")
+                                .value_name("DIR")
+                                .required(false)
+                                .help("Resolve project paths relative to DIR instead of the configuration file"),
+                        )
                         .about("List installed plugins"),
                 )
                 .subcommand(
@@ -209,6 +494,13 @@ fn build_cli() -> Command {
                                 .default_value(DEFAULT_TARS_CONFIG_FILE)
                                 .help("Specify the configuration file to use"),
                         )
+                        .arg(
+                            Arg::new("root")
+                                .long("root")
+                                .value_name("DIR")
+                                .required(false)
+                                .help("Resolve project paths relative to DIR instead of the configuration file"),
+                        )
                         .about("Verify plugin configuration"),
                 )
                 .subcommand(
@@ -229,57 +521,122 @@ fn build_cli() -> Command {
 }
 
 pub fn parse_args() -> Result<Args> {
+    parse_args_from(std::env::args_os())
+}
+
+fn parse_args_from<I, T>(args: I) -> Result<Args>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
     let cli = build_cli();
 
-    match cli.get_matches().subcommand() {
+    match cli.get_matches_from(args).subcommand() {
         Some(("init", args)) => {
             let force = args.get_flag("force");
             let path = args.get_one::<String>("path").unwrap();
+            let starter = args.get_one::<String>("starter").unwrap();
 
             Ok(Args {
                 subcommand: TarsSubcommand::Init(InitArgs {
                     force,
                     path: path.clone(),
+                    starter: starter.clone(),
+                    title: args.get_one::<String>("title").cloned(),
+                    author: args.get_one::<String>("author").cloned(),
+                    base_url: args.get_one::<String>("base_url").cloned(),
+                }),
+            })
+        }
+        Some(("new", args)) => {
+            let config = args.get_one::<String>("config").unwrap();
+            let root = args.get_one::<String>("root").cloned();
+            let path = args.get_one::<String>("path").unwrap();
+
+            Ok(Args {
+                subcommand: TarsSubcommand::New(NewArgs {
+                    config: config.clone(),
+                    root,
+                    path: path.clone(),
                 }),
             })
         }
         Some(("build", args)) => {
             let config = args.get_one::<String>("config").unwrap();
+            let root = args.get_one::<String>("root").cloned();
+            let env = args.get_one::<String>("env").cloned();
             let no_verify = args.get_flag("no_verify");
+            let force = args.get_flag("force");
 
             Ok(Args {
                 subcommand: TarsSubcommand::Build(BuildArgs {
                     config: config.clone(),
+                    root,
+                    env,
+                    overrides: OverrideArgs::from_matches(args),
                     no_verify,
+                    force,
                 }),
             })
         }
         Some(("clean", args)) => {
             let config = args.get_one::<String>("config").unwrap();
+            let root = args.get_one::<String>("root").cloned();
+            let env = args.get_one::<String>("env").cloned();
 
             Ok(Args {
                 subcommand: TarsSubcommand::Clean(CleanArgs {
                     config: config.clone(),
+                    root,
+                    env,
                 }),
             })
         }
         Some(("serve", args)) => {
             let config = args.get_one::<String>("config").unwrap();
+            let root = args.get_one::<String>("root").cloned();
+            let env = args.get_one::<String>("env").cloned();
 
             Ok(Args {
                 subcommand: TarsSubcommand::Serve(ServeArgs {
                     config: config.clone(),
+                    root,
+                    env,
+                    overrides: OverrideArgs::from_matches(args),
                 }),
             })
         }
+        Some(("config", args)) => Ok(Args {
+            subcommand: TarsSubcommand::Config(ConfigArgs {
+                subcommand: match args.subcommand() {
+                    Some(("show", args)) => {
+                        let config = args.get_one::<String>("config").unwrap();
+                        let root = args.get_one::<String>("root").cloned();
+                        let env = args.get_one::<String>("env").cloned();
+
+                        ConfigSubcommand::Show(ConfigShowArgs {
+                            config: config.clone(),
+                            root,
+                            env,
+                            overrides: OverrideArgs::from_matches(args),
+                        })
+                    }
+                    _ => {
+                        return Err(anyhow!("no subcommand specified"));
+                    }
+                },
+            }),
+        }),
         Some(("plugin", args)) => Ok(Args {
             subcommand: TarsSubcommand::Plugin(PluginArgs {
                 subcommand: match args.subcommand() {
                     Some(("list", args)) => {
                         let config = args.get_one::<String>("config").unwrap();
+                        let root = args.get_one::<String>("root").cloned();
 
                         PluginSubcommand::List(PluginListArgs {
                             config: config.clone(),
+                            root,
                         })
                     }
                     Some(("hash", args)) => {
@@ -293,9 +650,11 @@ pub fn parse_args() -> Result<Args> {
                     }
                     Some(("verify", args)) => {
                         let config = args.get_one::<String>("config").unwrap();
+                        let root = args.get_one::<String>("root").cloned();
 
                         PluginSubcommand::Verify(PluginVerifyArgs {
                             config: config.clone(),
+                            root,
                         })
                     }
                     _ => {
@@ -307,3 +666,46 @@ pub fn parse_args() -> Result<Args> {
         _ => Err(anyhow!("no subcommand specified")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, LoadOptions};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_set_string_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tars.toml");
+        fs::write(&path, "[site]\ntitle = \"Blog\"\n").unwrap();
+        let path = path.to_string_lossy();
+
+        let args = parse_args_from([
+            "tars",
+            "build",
+            "--config",
+            &path,
+            "--set",
+            "site.title=true",
+            "--set",
+            "site.author=2024",
+            "--set",
+            "build.jobs=2",
+        ])
+        .unwrap();
+
+        let TarsSubcommand::Build(args) = args.subcommand else {
+            panic!("expected the build subcommand");
+        };
+        let options = LoadOptions {
+            overrides: args.overrides.to_overrides().unwrap(),
+            ..Default::default()
+        };
+        let config = Config::from_file(&path, &options).unwrap();
+
+        assert_eq!(config.site.title.as_deref(), Some("true"));
+        assert_eq!(config.site.author.as_deref(), Some("2024"));
+        assert_eq!(config.build.jobs, 2);
+    }
+}
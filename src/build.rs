@@ -3,50 +3,192 @@ use fs_extra::{
     dir::{CopyOptions as DirOptions, copy as copy_dir},
     file::{CopyOptions as FileOptions, copy as copy_file},
 };
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
 use std::{
-    fs::{create_dir_all, remove_dir_all, write},
-    path::{Path, PathBuf},
+    collections::{BTreeSet, HashMap},
+    fmt,
+    fs::{copy, create_dir_all, hard_link, remove_dir_all, remove_file, rename, write},
+    io::ErrorKind,
+    path::{Path, PathBuf, absolute},
+    sync::Arc,
+    time::Instant,
 };
-use tempfile::{TempDir, tempdir};
+use tempfile::TempDir;
 use walkdir::WalkDir;
 
 use crate::{
-    config::{Config, HookType},
+    cache::{BuildCache, CachedPage, FileHashes, hash_dirs},
+    config::{Config, HookType, Roots},
     markdown::Page,
+    menu::Menus,
+    output::{MemoryOutput, OutputFile},
     template::{TemplateContext, TemplateEnvironment},
 };
 
+/// Source files changed since the last build, grouped by how they affect the
+/// generated site.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Content files, relative to the content directory.
+    pub content: BTreeSet<PathBuf>,
+    /// Template names.
+    pub templates: BTreeSet<String>,
+    /// Static files, relative to the static directory.
+    pub statics: BTreeSet<PathBuf>,
+    /// Whether a change needs a full rebuild, such as to data files or
+    /// plugins.
+    pub full: bool,
+    /// Whether a configuration file changed, so the configuration must be
+    /// loaded again.
+    pub config: bool,
+}
+
+impl Changes {
+    /// Classify a changed path by the source directory containing it.
+    pub fn add(&mut self, roots: &Roots, path: &Path) {
+        let Ok(path) = absolute(path) else {
+            self.full = true;
+            return;
+        };
+        let relative_to = |root: &PathBuf| {
+            let root = absolute(root).ok()?;
+            path.strip_prefix(root).ok().map(Path::to_path_buf)
+        };
+
+        if let Some(rel_path) = relative_to(&roots.content) {
+            self.content.insert(rel_path);
+        } else if let Some(rel_path) = roots.templates.iter().find_map(relative_to) {
+            let name: Vec<_> = rel_path.iter().map(|c| c.to_string_lossy()).collect();
+            self.templates.insert(name.join("/"));
+        } else if let Some(rel_path) = roots.statics.iter().find_map(relative_to) {
+            self.statics.insert(rel_path);
+        } else {
+            self.full = true;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.full
+            && !self.config
+            && self.content.is_empty()
+            && self.templates.is_empty()
+            && self.statics.is_empty()
+    }
+}
+
 pub struct Builder<'a> {
     template_env: TemplateEnvironment<'a>,
     pages: Vec<Page>,
+    menus: Menus,
     config: &'a Config,
-    content_root: PathBuf,
-    static_root: PathBuf,
+    roots: Roots,
     build_root: PathBuf,
-    template_root: PathBuf,
     config_path: PathBuf,
     tmp_dir: Option<TempDir>,
+    /// Generated files, when building in memory rather than to the build
+    /// directory.
+    memory: Option<Arc<MemoryOutput>>,
+    /// Files generated by a full build in memory, which replace the served
+    /// files once it succeeds.
+    staged: MemoryOutput,
+    /// The workspace of the last build in memory with plugins, which its
+    /// files are served from.
+    served_dir: Option<TempDir>,
+    pool: Option<ThreadPool>,
+    cache: BuildCache,
+    prev_cache: Option<BuildCache>,
+    /// Hashes of source files, kept between builds.
+    hashes: FileHashes,
     built: bool,
     no_verify: bool,
-    extra_content: Option<&'a str>,
+    force: bool,
 }
 
 impl<'a> Builder<'a> {
-    pub fn new(config: &'a Config, no_verify: bool, extra_content: Option<&'a str>) -> Self {
+    pub fn new(config: &'a Config, no_verify: bool, force: bool) -> Self {
         Self {
             template_env: TemplateEnvironment::new(),
             pages: Vec::new(),
+            menus: Menus::default(),
             config,
-            content_root: PathBuf::new(),
-            static_root: PathBuf::new(),
+            roots: Roots::default(),
             build_root: PathBuf::new(),
-            template_root: PathBuf::new(),
             config_path: PathBuf::new(),
             tmp_dir: None,
+            memory: None,
+            staged: MemoryOutput::default(),
+            served_dir: None,
+            pool: None,
+            cache: BuildCache::new(),
+            prev_cache: None,
+            hashes: FileHashes::default(),
             built: false,
             no_verify: no_verify | config.build.no_verify,
-            extra_content,
+            force,
+        }
+    }
+
+    /// Returns a builder which generates the site into `output` rather than
+    /// the build directory, which is left untouched.
+    pub fn in_memory(config: &'a Config, output: Arc<MemoryOutput>) -> Self {
+        Self {
+            memory: Some(output),
+            ..Self::new(config, false, false)
+        }
+    }
+
+    /// Returns the in-memory output to write to, if any.
+    ///
+    /// Plugins need the generated files on disk, so with plugins the site is
+    /// generated in a temporary directory, and served from there.
+    fn memory_output(&self) -> Option<&MemoryOutput> {
+        let memory = self.memory.as_deref()?;
+
+        if !self.config.plugins.is_empty() {
+            None
+        } else if self.built {
+            Some(memory)
+        } else {
+            Some(&self.staged)
+        }
+    }
+
+    /// Write a generated file, at `rel_path` relative to the build root.
+    fn write_output(&self, rel_path: &Path, contents: String) -> Result<()> {
+        if let Some(memory) = self.memory_output() {
+            memory.insert(rel_path.to_path_buf(), OutputFile::Data(contents.into()));
+            return Ok(());
+        }
+
+        let dst_path = self.build_root.join(rel_path);
+        if let Some(parent) = dst_path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        // Replace rather than overwrite, since the output may be linked to a
+        // previous build.
+        remove_existing(&dst_path)?;
+        write(&dst_path, contents)?;
+
+        Ok(())
+    }
+
+    /// Remove a generated file, or directory, at `rel_path` relative to the
+    /// build root.
+    fn remove_output(&self, rel_path: &Path) -> Result<()> {
+        if let Some(memory) = self.memory_output() {
+            memory.remove(rel_path);
+            return Ok(());
         }
+
+        let dst_path = self.build_root.join(rel_path);
+        if dst_path.is_dir() {
+            remove_dir_all(&dst_path)?;
+        } else {
+            remove_existing(&dst_path)?;
+        }
+
+        Ok(())
     }
 
     pub fn run_pre_plugins(&self) -> Result<()> {
@@ -80,45 +222,64 @@ impl<'a> Builder<'a> {
     }
 
     pub fn clean(&self) -> Result<()> {
-        let build_root = PathBuf::from(&self.config.build.build_dir);
+        let build_root = self.config.project_path(&self.config.build.build_dir);
+        let cache_dir = self.config.project_path(&self.config.build.cache_dir);
 
-        // Clean any existing build
+        // Clean any existing build, and the cache describing it
         if build_root.is_dir() {
             remove_dir_all(build_root)?;
         }
+        if cache_dir.is_dir() {
+            remove_dir_all(cache_dir)?;
+        }
 
         Ok(())
     }
 
     pub fn copy_static(&self) -> Result<()> {
-        // Copy the static content directory
-        if self.static_root.is_dir() {
-            let static_dst = self.build_root.join(&self.config.build.static_prefix);
-            if !static_dst.is_dir() {
+        let static_dst = self.build_root.join(&self.config.build.static_prefix);
+        let memory = self.memory_output();
+
+        // Copy theme static content first, so project files take precedence.
+        for static_root in self.roots.statics.iter().rev() {
+            if !static_root.is_dir() {
+                continue;
+            }
+
+            if memory.is_none() && !static_dst.is_dir() {
                 create_dir_all(&static_dst)?;
             }
 
-            for entry in std::fs::read_dir(&self.static_root)? {
+            // Merge into the destination, overwriting existing files.
+            for entry in WalkDir::new(static_root) {
                 let entry = entry?;
                 let src_path = entry.path();
-                let dst_path = static_dst.join(entry.file_name());
-
-                if src_path.is_dir() {
-                    let mut options = DirOptions::new();
-                    options.overwrite = true;
-                    options.copy_inside = true;
+                let rel_path = src_path.strip_prefix(static_root)?;
+                let dst_path = static_dst.join(rel_path);
 
-                    copy_dir(&src_path, &dst_path, &options)?;
-                } else if src_path.is_file() {
-                    let mut options = FileOptions::new();
-                    options.overwrite = true;
-
-                    copy_file(&src_path, &dst_path, &options)?;
+                // Static files are served from their sources, in memory.
+                if let Some(memory) = memory {
+                    if entry.file_type().is_file() {
+                        let rel_path = Path::new(&self.config.build.static_prefix).join(rel_path);
+                        memory.insert(rel_path, OutputFile::Path(absolute(src_path)?));
+                    }
+                } else if entry.file_type().is_dir() {
+                    create_dir_all(&dst_path)?;
+                } else if entry.file_type().is_file() {
+                    // Post-hook plugins may modify output files in place,
+                    // which mustn't change the sources they're linked to.
+                    if self.has_plugins(HookType::Post) {
+                        remove_existing(&dst_path)?;
+                        copy(src_path, &dst_path)?;
+                    } else {
+                        link_or_copy(src_path, &dst_path)?;
+                    }
                 }
             }
 
             println!(
-                "Copied static content directory to {}",
+                "Copied static content directory {} to {}",
+                static_root.display(),
                 self.config.build.static_prefix
             );
         }
@@ -126,88 +287,282 @@ impl<'a> Builder<'a> {
         Ok(())
     }
 
+    fn pool(&self) -> Result<&ThreadPool> {
+        self.pool
+            .as_ref()
+            .ok_or(anyhow!("build thread pool not set"))
+    }
+
+    /// Returns the project-relative path of a content file, for messages.
+    fn source_name(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.roots.content) {
+            Ok(rel_path) => Path::new(&self.config.build.content_dir).join(rel_path),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    fn print_warnings(&self, page: &Page) {
+        for warning in &page.warnings {
+            println!(
+                "Warning: {}: {warning}",
+                self.source_name(&page.path).display()
+            );
+        }
+    }
+
+    /// Fail if two pages generate the same file, e.g. `post.md` and
+    /// `post.en.md` when English is the default language.
+    fn check_output_paths(&self) -> Result<()> {
+        let mut sources: HashMap<&Path, &Path> = HashMap::new();
+
+        let pages = self
+            .pages
+            .iter()
+            .filter(|page| self.config.build.include_drafts || !page.meta.draft);
+
+        for page in pages {
+            if let Some(other) = sources.insert(&page.rel_path, &page.path) {
+                return Err(anyhow!(
+                    "{} and {} both generate {}",
+                    self.source_name(other).display(),
+                    self.source_name(&page.path).display(),
+                    page.rel_path.display()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn load_pages(&mut self) -> Result<()> {
-        for entry in WalkDir::new(&self.content_root) {
+        // Sorted, so page order doesn't depend on the filesystem.
+        let mut paths = Vec::new();
+        for entry in WalkDir::new(&self.roots.content).sort_by_file_name() {
             let entry = entry?;
-            let entry_type = entry.file_type();
-            let path = entry.path();
-            let rel_path = path.strip_prefix(&self.content_root)?;
-            let dst_path = self.build_root.join(rel_path);
 
-            if entry_type.is_file() {
-                let page = Page::from_file(self.config, &self.content_root, &path.to_path_buf())?;
-                self.pages.push(page);
-            } else if entry_type.is_dir() {
-                create_dir_all(dst_path)?;
+            if entry.file_type().is_file() {
+                paths.push(entry.into_path());
             }
         }
 
+        let config = self.config;
+        let content_root = &self.roots.content;
+        let results: Vec<_> = self.pool()?.install(|| {
+            paths
+                .par_iter()
+                .map(|path| Page::from_file(config, content_root, path))
+                .collect()
+        });
+
+        let names: Vec<_> = paths.iter().map(|p| self.source_name(p)).collect();
+        self.pages = collect_results(&names, results)?;
+        for page in &self.pages {
+            self.print_warnings(page);
+        }
+        self.check_output_paths()?;
+
         Ok(())
     }
 
-    pub fn generate_pages(&mut self) -> Result<()> {
-        for i in 0..self.pages.len() {
-            let page = &self.pages[i];
+    /// Hash the inputs of this build, and load the cache of the previous one.
+    ///
+    /// The previous cache is ignored for forced builds, builds in memory, and
+    /// when post-build plugins are configured, since they may modify any
+    /// generated file.
+    ///
+    /// Files are only hashed again when their size or modification time
+    /// differs from the previous build's.
+    fn load_cache(&mut self) -> Result<()> {
+        let build_dst = self.config.project_path(&self.config.build.build_dir);
+        let saved = match self.memory {
+            Some(_) => None,
+            None => BuildCache::load(&self.config.project_path(&self.config.build.cache_dir)),
+        };
 
-            if !self.config.build.include_drafts && page.meta.draft {
-                continue;
-            }
+        if let Some(saved) = &saved {
+            self.hashes.extend(&saved.files);
+        }
+
+        let mut cache = BuildCache::new();
+
+        let global_data = hash_dirs(&[&self.roots.data, &self.roots.i18n], &self.hashes)?;
+        cache.global = sha256::digest(format!("{}\n{}", self.config.to_toml()?, global_data));
+
+        cache.site = self.site_hash()?;
+        cache.content = hash_dirs(&[&self.roots.content], &self.hashes)?;
+
+        let statics: Vec<&Path> = self.roots.statics.iter().map(PathBuf::as_path).collect();
+        cache.statics = hash_dirs(&statics, &self.hashes)?;
+
+        self.prev_cache = if self.force || self.has_plugins(HookType::Post) || !build_dst.is_dir() {
+            None
+        } else {
+            saved
+        };
+        self.cache = cache;
+
+        Ok(())
+    }
+
+    /// Returns a hash of the path and front matter of every page.
+    fn site_hash(&self) -> Result<String> {
+        let site: Vec<_> = self
+            .pages
+            .iter()
+            .map(|p| (&p.rel_path, &p.lang, &p.meta))
+            .collect();
+
+        Ok(sha256::digest(serde_json::to_string(&site)?))
+    }
+
+    pub fn generate_pages(&mut self) -> Result<()> {
+        self.render_pages(None)
+    }
 
-            let ctx = TemplateContext::new(&self.pages, page);
+    /// Render pages, or only those with output paths in `only`.
+    fn render_pages(&mut self, only: Option<&BTreeSet<PathBuf>>) -> Result<()> {
+        let pages: Vec<&Page> = self
+            .pages
+            .iter()
+            .filter(|page| self.config.build.include_drafts || !page.meta.draft)
+            .filter(|page| only.is_none_or(|only| only.contains(&page.rel_path)))
+            .collect();
 
-            let mut dst_path = self.build_root.join(&page.rel_path);
-            dst_path.set_extension("html");
+        let results: Vec<_> = self.pool()?.install(|| {
+            pages
+                .par_iter()
+                .map(|page| self.generate_page(page))
+                .collect()
+        });
 
-            let extra_str = self.extra_content.unwrap_or("");
+        let names: Vec<_> = pages.iter().map(|p| self.source_name(&p.path)).collect();
+        let results = collect_results(&names, results)?;
 
-            if let Some(tmpl_name) = &page.meta.template {
-                let render_str = self.template_env.render_template(&ctx, tmpl_name)?;
-                write(&dst_path, format!("{}{}", render_str, extra_str))?;
+        let mut unchanged = 0;
+        for (page, (cached, generated)) in pages.into_iter().zip(results) {
+            if generated {
+                println!("Generated {}", &page.rel_path.display());
             } else {
-                write(&dst_path, format!("{}{}", &page.content, extra_str))?;
+                unchanged += 1;
             }
 
-            println!("Generated {}", &page.rel_path.display());
+            self.cache.pages.insert(page.rel_path.clone(), cached);
+        }
+
+        if unchanged > 0 {
+            println!("Skipped {unchanged} unchanged pages");
         }
 
         Ok(())
     }
 
+    /// Render a page, unless it's unchanged since the previous build.
+    ///
+    /// Returns the page's cache entry, and whether it was rendered.
+    fn generate_page(&self, page: &Page) -> Result<(CachedPage, bool)> {
+        let mut cached = CachedPage {
+            source: self.hashes.digest(&page.path)?,
+            ..Default::default()
+        };
+
+        if let Some(tmpl_name) = &page.meta.template {
+            let deps = self.template_env.dependencies(tmpl_name)?;
+            cached.templates = deps.templates;
+            cached.pages = deps.pages;
+            cached.assets = deps.assets;
+            cached.dynamic = deps.dynamic;
+        }
+
+        if let Some(prev) = &self.prev_cache {
+            let build_dst = self.config.project_path(&self.config.build.build_dir);
+            let exists = build_dst.join(&page.rel_path).is_file();
+
+            if exists && !self.cache.is_stale(prev, &page.rel_path, &cached) {
+                return Ok((cached, false));
+            }
+        }
+
+        let ctx = TemplateContext::new(self.config, &self.pages, page, &self.menus);
+
+        let mut rel_path = page.rel_path.clone();
+        rel_path.set_extension("html");
+
+        if let Some(tmpl_name) = &page.meta.template {
+            let render_str = self.template_env.render_template(&ctx, tmpl_name)?;
+            self.write_output(&rel_path, render_str)?;
+        } else {
+            self.write_output(&rel_path, page.content.clone())?;
+        }
+
+        Ok((cached, true))
+    }
+
+    fn has_plugins(&self, hook_type: HookType) -> bool {
+        self.config.plugins.iter().any(|p| p.hook_type == hook_type)
+    }
+
+    /// Returns the configured build directory, split into its parent
+    /// directory and name.
+    fn build_dst(&self) -> Result<(PathBuf, PathBuf, String)> {
+        let build_dst = self.config.project_path(&self.config.build.build_dir);
+        let (Some(parent), Some(build_name)) = (build_dst.parent(), build_dst.file_name()) else {
+            return Err(anyhow!("build path must be a valid directory"));
+        };
+        let build_name = build_name.to_string_lossy().into_owned();
+
+        Ok((parent.to_path_buf(), build_dst.clone(), build_name))
+    }
+
+    /// Create the temporary directory the build is generated in, and which
+    /// plugins run in.
+    ///
+    /// It's created next to the build directory, so the output can be moved
+    /// into place rather than copied. Sources are read in place, unless
+    /// pre-hook plugins are configured, which may generate or modify content,
+    /// templates and static files, so run on copies of them.
+    ///
+    /// Builds in memory use a system temporary directory instead, so nothing
+    /// is written next to the build directory.
     fn prepare_build_dir(&mut self) -> Result<()> {
-        let tmp_root = tempdir()?;
+        let (parent, _, build_name) = self.build_dst()?;
+
+        let tmp_root = if self.memory.is_some() {
+            tempfile::tempdir()?
+        } else {
+            create_dir_all(&parent)?;
+            tempfile::Builder::new()
+                .prefix(&format!(".{build_name}.new-"))
+                .tempdir_in(&parent)?
+        };
 
         println!("Build directory: {}", tmp_root.path().display());
 
-        fn push_and_copy(src: &Path, dst: &mut PathBuf, kind: &str) -> Result<()> {
+        fn copy_to_tmp(src: &Path, tmp_root: &Path, kind: &str) -> Result<PathBuf> {
             let name = src
                 .file_name()
                 .ok_or_else(|| anyhow!("{} path must be a valid directory", kind))?;
-            dst.push(name);
+            let dst = tmp_root.join(name);
 
             let mut options = DirOptions::new();
             options.overwrite = true;
             options.copy_inside = true;
 
             if src.is_dir() {
-                copy_dir(src, dst, &options)?;
+                copy_dir(src, &dst, &options)?;
             }
-            Ok(())
+            Ok(dst)
         }
 
-        let content_dir = Path::new(&self.config.build.content_dir);
-        let static_dir = Path::new(&self.config.build.static_dir);
-        let template_dir = Path::new(&self.config.build.template_dir);
+        let mut roots = self.config.roots();
 
-        self.content_root.push(tmp_root.path());
-        self.static_root.push(tmp_root.path());
-        self.template_root.push(tmp_root.path());
-
-        self.build_root.push(tmp_root.path());
-        self.build_root.push(&self.config.build.build_dir);
+        if self.has_plugins(HookType::Pre) {
+            roots.content = copy_to_tmp(&roots.content, tmp_root.path(), "content")?;
+            roots.statics[0] = copy_to_tmp(&roots.statics[0], tmp_root.path(), "static")?;
+            roots.templates[0] = copy_to_tmp(&roots.templates[0], tmp_root.path(), "template")?;
+        }
 
-        push_and_copy(content_dir, &mut self.content_root, "content")?;
-        push_and_copy(static_dir, &mut self.static_root, "static")?;
-        push_and_copy(template_dir, &mut self.template_root, "template")?;
+        self.roots = roots;
+        self.build_root = tmp_root.path().join(build_name);
 
         let mut config_path = PathBuf::from(tmp_root.path());
         config_path.push("tars.toml");
@@ -224,15 +579,56 @@ impl<'a> Builder<'a> {
         Ok(())
     }
 
-    fn copy_generated(&self) -> Result<()> {
-        let build_dst = Path::new(&self.config.build.build_dir);
+    /// Move the generated output into place and save the cache.
+    ///
+    /// The previous build is left intact until the new one is complete.
+    /// Builds in memory replace the served files instead.
+    fn copy_generated(&mut self) -> Result<()> {
+        if let Some(memory) = &self.memory {
+            // With plugins, the generated files are served from the workspace,
+            // which is kept until the next build replaces them.
+            if !self.config.plugins.is_empty() {
+                for entry in WalkDir::new(&self.build_root) {
+                    let entry = entry?;
 
-        let mut options = DirOptions::new();
-        options.overwrite = true;
-        options.copy_inside = true;
-        options.content_only = true;
+                    if entry.file_type().is_file() {
+                        let rel_path = entry.path().strip_prefix(&self.build_root)?;
+                        self.staged
+                            .insert(rel_path.to_path_buf(), OutputFile::Path(entry.into_path()));
+                    }
+                }
+            }
+
+            memory.replace(&self.staged);
+            self.served_dir = self.tmp_dir.take();
 
-        copy_dir(&self.build_root, build_dst, &options)?;
+            return Ok(());
+        }
+
+        let (_, build_dst, _) = self.build_dst()?;
+
+        // Keep pages which an incremental build skipped.
+        if self.prev_cache.is_some() {
+            for rel_path in self.cache.pages.keys() {
+                let dst_path = self.build_root.join(rel_path);
+                if dst_path.is_file() {
+                    continue;
+                }
+
+                if let Some(parent) = dst_path.parent() {
+                    create_dir_all(parent)?;
+                }
+                link_or_copy(&build_dst.join(rel_path), &dst_path)?;
+            }
+        }
+
+        replace_dir(&self.build_root, &build_dst)?;
+
+        self.tmp_dir = None;
+
+        self.cache.files = self.hashes.stamps();
+        self.cache
+            .save(&self.config.project_path(&self.config.build.cache_dir))?;
 
         Ok(())
     }
@@ -245,9 +641,184 @@ impl<'a> Builder<'a> {
         self.build()
     }
 
+    /// Update the build directory for changed sources, rendering only the
+    /// pages they affect.
+    ///
+    /// This falls back to a full rebuild if the previous build failed, or
+    /// plugins are configured, since they may generate or modify any file.
+    pub fn update(&mut self, changes: &Changes) -> Result<()> {
+        if changes.full || changes.config || !self.built || !self.config.plugins.is_empty() {
+            return self.rebuild();
+        }
+
+        let start = Instant::now();
+        let (_, build_dst, _) = self.build_dst()?;
+
+        // Sources are read in place without plugins, so the previous build's
+        // state can be updated, and pages rendered straight into the output.
+        self.build_root = build_dst;
+        self.prev_cache = None;
+
+        for rel_path in &changes.statics {
+            self.update_static(rel_path)?;
+        }
+
+        if !changes.templates.is_empty() {
+            self.template_env.clear_templates();
+        }
+
+        let site_hash = self.site_hash()?;
+        let mut stale = BTreeSet::new();
+
+        for rel_path in &changes.content {
+            let src_path = self.roots.content.join(rel_path);
+
+            // New directories may contain files without their own events.
+            if src_path.is_dir() {
+                return self.rebuild();
+            }
+
+            // Remove pages from the changed file, or a removed directory.
+            let (removed, pages): (Vec<Page>, Vec<Page>) = std::mem::take(&mut self.pages)
+                .into_iter()
+                .partition(|page| {
+                    page.path
+                        .strip_prefix(&self.roots.content)
+                        .is_ok_and(|p| p.starts_with(rel_path))
+                });
+            self.pages = pages;
+
+            for page in removed {
+                self.remove_output(&page.rel_path)?;
+                self.cache.pages.remove(&page.rel_path);
+            }
+
+            if src_path.is_file() {
+                let page =
+                    Page::from_file(self.config, &self.roots.content, &src_path).map_err(|e| {
+                        let name = self.source_name(&src_path).display().to_string();
+                        PageErrors(vec![(name, e)])
+                    })?;
+                self.print_warnings(&page);
+                stale.insert(page.rel_path.clone());
+                self.pages.push(page);
+            }
+        }
+
+        self.pages.sort_by(|a, b| a.path.cmp(&b.path));
+        self.check_output_paths()?;
+        self.menus = Menus::new(self.config, &self.pages)?;
+
+        let site_changed = self.site_hash()? != site_hash;
+        let content_changed = !changes.content.is_empty();
+        let statics_changed = !changes.statics.is_empty();
+        let uses_changed_template = |name: &String| {
+            changes
+                .templates
+                .iter()
+                .any(|t| name == t || name.starts_with(&format!("{t}/")))
+        };
+
+        for page in &self.pages {
+            let affected = match self.cache.pages.get(&page.rel_path) {
+                Some(cached) => {
+                    cached.dynamic
+                        || cached.templates.keys().any(uses_changed_template)
+                        || (!cached.templates.is_empty()
+                            && (site_changed
+                                || (cached.pages && content_changed)
+                                || (cached.assets && statics_changed)))
+                }
+                None => true,
+            };
+
+            if affected {
+                stale.insert(page.rel_path.clone());
+            }
+        }
+
+        self.render_pages(Some(&stale))?;
+
+        println!("Updated in {} ms", start.elapsed().as_millis());
+
+        Ok(())
+    }
+
+    /// Update a static file in the build directory, from the first static
+    /// directory containing it.
+    fn update_static(&self, rel_path: &Path) -> Result<()> {
+        let dst_rel_path = Path::new(&self.config.build.static_prefix).join(rel_path);
+        let dst_path = self.build_root.join(&dst_rel_path);
+        let src_path = self
+            .roots
+            .statics
+            .iter()
+            .map(|root| root.join(rel_path))
+            .find(|path| path.exists());
+
+        if let Some(memory) = self.memory_output() {
+            memory.remove(&dst_rel_path);
+            match src_path {
+                Some(src_path) if src_path.is_dir() => {
+                    for entry in WalkDir::new(&src_path) {
+                        let entry = entry?;
+
+                        if entry.file_type().is_file() {
+                            let rel_path = entry.path().strip_prefix(&src_path)?;
+                            memory.insert(
+                                dst_rel_path.join(rel_path),
+                                OutputFile::Path(absolute(entry.path())?),
+                            );
+                        }
+                    }
+                }
+                Some(src_path) => {
+                    memory.insert(dst_rel_path, OutputFile::Path(absolute(&src_path)?));
+                    println!("Copied {}", rel_path.display());
+                }
+                None => {}
+            }
+
+            return Ok(());
+        }
+
+        match src_path {
+            Some(src_path) if src_path.is_dir() => create_dir_all(&dst_path)?,
+            Some(src_path) => {
+                if let Some(parent) = dst_path.parent() {
+                    create_dir_all(parent)?;
+                }
+                link_or_copy(&src_path, &dst_path)?;
+                println!("Copied {}", rel_path.display());
+            }
+            None if dst_path.is_dir() => remove_dir_all(&dst_path)?,
+            None => remove_existing(&dst_path)?,
+        }
+
+        Ok(())
+    }
+
     pub fn build(&mut self) -> Result<()> {
+        let result = self.run_build();
+
+        // Remove the staging directory of a failed build.
+        if result.is_err() {
+            self.tmp_dir = None;
+        }
+
+        result
+    }
+
+    fn run_build(&mut self) -> Result<()> {
+        if self.pool.is_none() {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(self.config.build.jobs)
+                .build()?;
+            self.pool = Some(pool);
+        }
+
         self.prepare_build_dir()?;
-        self.clean()?;
+        self.staged = MemoryOutput::default();
 
         create_dir_all(&self.build_root)?;
 
@@ -255,8 +826,10 @@ impl<'a> Builder<'a> {
 
         self.run_pre_plugins()?;
 
-        self.template_env.load_templates(self.config)?;
+        self.template_env.load_templates(self.config, &self.roots)?;
         self.load_pages()?;
+        self.menus = Menus::new(self.config, &self.pages)?;
+        self.load_cache()?;
         self.generate_pages()?;
 
         self.run_post_plugins()?;
@@ -268,3 +841,363 @@ impl<'a> Builder<'a> {
         Ok(())
     }
 }
+
+/// Errors of the files which failed to load or render, by source name.
+#[derive(Debug)]
+pub struct PageErrors(pub Vec<(String, anyhow::Error)>);
+
+impl fmt::Display for PageErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.len() > 1 {
+            writeln!(f, "{} pages failed:", self.0.len())?;
+        }
+
+        let lines: Vec<_> = self
+            .0
+            .iter()
+            .map(|(file, e)| format!("{file}: {e}"))
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for PageErrors {}
+
+/// Collect per-file results in order, or an error listing every file which
+/// failed.
+fn collect_results<T, P: AsRef<Path>>(paths: &[P], results: Vec<Result<T>>) -> Result<Vec<T>> {
+    let mut values = Vec::with_capacity(results.len());
+    let mut errors = Vec::new();
+
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(value) => values.push(value),
+            Err(e) => errors.push((path.as_ref().display().to_string(), e)),
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(values),
+        false => Err(PageErrors(errors).into()),
+    }
+}
+
+/// Replace the build directory `dst` with the new build in `src`.
+///
+/// The previous build is moved aside and replaced with two renames, so
+/// there's a brief window with no build directory. If the second rename
+/// fails, the previous build is moved back.
+fn replace_dir(src: &Path, dst: &Path) -> Result<()> {
+    let (Some(parent), Some(name)) = (dst.parent(), dst.file_name()) else {
+        return Err(anyhow!("build path must be a valid directory"));
+    };
+    let build_name = name.to_string_lossy();
+
+    // Move the previous build aside, replacing an empty directory which
+    // is removed with it when dropped.
+    let previous = tempfile::Builder::new()
+        .prefix(&format!(".{build_name}.old-"))
+        .tempdir_in(parent)?;
+
+    let has_previous = dst.is_dir();
+    if has_previous {
+        rename(dst, previous.path())?;
+    }
+
+    if let Err(e) = rename(src, dst) {
+        let restored = match has_previous {
+            true => rename(previous.path(), dst),
+            false => Ok(()),
+        };
+
+        // Never delete the previous build if it couldn't be moved back.
+        if let Err(restore_err) = restored {
+            let kept = previous.keep();
+            return Err(anyhow!(
+                "failed to move build into {}: {e}; the previous build was kept in {}: {restore_err}",
+                dst.display(),
+                kept.display()
+            ));
+        }
+
+        return Err(anyhow!("failed to move build into {}: {e}", dst.display()));
+    }
+    drop(previous);
+
+    Ok(())
+}
+
+/// Remove the file at `path`, if any.
+///
+/// Output files may be hard links to sources, so must be replaced rather than
+/// written to.
+fn remove_existing(path: &Path) -> Result<()> {
+    match remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Hard link `src` to `dst`, or copy it if linking isn't supported.
+fn link_or_copy(src: &Path, dst: &Path) -> Result<()> {
+    remove_existing(dst)?;
+
+    if hard_link(src, dst).is_err() {
+        copy(src, dst)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LoadOptions;
+    use std::fs::{self, read_dir, read_to_string, write};
+    use tempfile::{TempDir, tempdir};
+
+    const CONFIG: &str = "[site]\ntitle = \"Test\"\n";
+
+    /// Write a project with `files` to a temporary directory.
+    fn write_project(files: &[(&str, &str)]) -> TempDir {
+        let root = tempdir().unwrap();
+        for (path, contents) in files {
+            let path = root.path().join(path);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, contents).unwrap();
+        }
+
+        root
+    }
+
+    fn load_config(root: &TempDir) -> Config {
+        let config_path = root.path().join("tars.toml");
+        Config::from_file(config_path.to_str().unwrap(), &LoadOptions::default()).unwrap()
+    }
+
+    /// Returns the names of entries in `dir` starting with `prefix`.
+    fn entries(dir: &Path, prefix: &str) -> Vec<String> {
+        read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(prefix))
+            .collect()
+    }
+
+    #[test]
+    fn test_failed_build_removes_staging_dir() {
+        let root = write_project(&[
+            ("tars.toml", CONFIG),
+            ("content/index.md", "---\ntemplate: page.html\n---\nHi\n"),
+            ("template/page.html", "{{ broken"),
+        ]);
+        let config = load_config(&root);
+        let mut builder = Builder::new(&config, false, false);
+
+        assert!(builder.build().is_err());
+        assert_eq!(entries(root.path(), ".build.new-"), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pre_plugins_modify_staged_sources() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let plugin = "[[plugin]]\nhook = \"pre\"\nname = \"stage.sh\"\nhash = \"\"\n";
+        let root = write_project(&[
+            ("tars.toml", &format!("{CONFIG}{plugin}")),
+            ("content/index.md", "Source\n"),
+            ("template/default.html", "<main>{{ content }}</main>"),
+            (
+                "plugin/stage.sh",
+                "#!/bin/sh\necho Staged >> content/index.md\necho '<p>{{ content|safe }}</p>' > template/default.html\n",
+            ),
+        ]);
+        let script = root.path().join("plugin/stage.sh");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = load_config(&root);
+
+        Builder::new(&config, true, false).build().unwrap();
+
+        let output = read_to_string(root.path().join("build/index.html")).unwrap();
+        assert_eq!(output, "<p><p>Source\nStaged</p>\n</p>");
+        assert_eq!(
+            read_to_string(root.path().join("content/index.md")).unwrap(),
+            "Source\n"
+        );
+        assert_eq!(
+            read_to_string(root.path().join("template/default.html")).unwrap(),
+            "<main>{{ content }}</main>"
+        );
+
+        // A failing plugin leaves the previous build in place.
+        write(&script, "#!/bin/sh\nexit 1\n").unwrap();
+        assert!(Builder::new(&config, true, false).build().is_err());
+        assert_eq!(
+            read_to_string(root.path().join("build/index.html")).unwrap(),
+            output
+        );
+        assert_eq!(entries(root.path(), ".build.new-"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parallel_build_reports_every_failure() {
+        let broken = "---\ntemplate: broken.html\n---\n";
+        let root = write_project(&[
+            ("tars.toml", &format!("{CONFIG}\n[build]\njobs = 4\n")),
+            ("content/a.md", broken),
+            ("content/b.md", "B\n"),
+            ("content/c/d.md", broken),
+            ("content/e.md", "E\n"),
+            ("template/broken.html", "{{ broken"),
+        ]);
+        let config = load_config(&root);
+
+        let err = Builder::new(&config, false, false).build().unwrap_err();
+        let errors = err.downcast_ref::<PageErrors>().unwrap();
+        let files: Vec<_> = errors.0.iter().map(|(file, _)| file.as_str()).collect();
+        assert_eq!(files, ["content/a.md", "content/c/d.md"]);
+        assert!(
+            err.to_string()
+                .starts_with("2 pages failed:\ncontent/a.md: ")
+        );
+        assert!(!root.path().join("build").exists());
+    }
+
+    #[test]
+    fn test_replace_dir() {
+        let root = write_project(&[("build/old.html", "old"), ("new/new.html", "new")]);
+        let build = root.path().join("build");
+
+        replace_dir(&root.path().join("new"), &build).unwrap();
+        assert_eq!(entries(&build, ""), ["new.html"]);
+        assert_eq!(entries(root.path(), "."), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_replace_dir_restores_previous_build() {
+        let root = write_project(&[("build/old.html", "old")]);
+        let build = root.path().join("build");
+
+        let err = replace_dir(&root.path().join("missing"), &build).unwrap_err();
+        assert!(
+            err.to_string().starts_with("failed to move build into"),
+            "{err}"
+        );
+        assert_eq!(read_to_string(build.join("old.html")).unwrap(), "old");
+        assert_eq!(entries(root.path(), "."), Vec::<String>::new());
+
+        let other = root.path().join("other");
+        assert!(replace_dir(&root.path().join("missing"), &other).is_err());
+        assert!(!other.exists());
+        assert_eq!(entries(root.path(), "."), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_collect_results() {
+        let paths = ["a.md", "b.md", "c.md"];
+        let values = collect_results(&paths, vec![Ok(1), Ok(2), Ok(3)]).unwrap();
+        assert_eq!(values, [1, 2, 3]);
+
+        let results = vec![Ok(1), Err(anyhow!("bad")), Ok(3)];
+        let err = collect_results(&paths, results).unwrap_err();
+        assert_eq!(err.to_string(), "b.md: bad");
+    }
+
+    #[test]
+    fn test_language_output_paths() {
+        let languages =
+            "[[language]]\ncode = \"en\"\ndefault = true\n\n[[language]]\ncode = \"de\"\n";
+        let root = write_project(&[
+            ("tars.toml", &format!("{CONFIG}{languages}")),
+            ("content/post.md", "Post\n"),
+            ("content/post.de.md", "Beitrag\n"),
+            ("content/de/about.md", "Über\n"),
+        ]);
+        let config = load_config(&root);
+        let mut builder = Builder::new(&config, false, false);
+        builder.build().unwrap();
+
+        let build = root.path().join("build");
+        assert!(build.join("post.html").is_file());
+        assert!(build.join("de/post.html").is_file());
+        assert!(build.join("de/about.html").is_file());
+
+        let duplicate = "content/post.en.md and content/post.md both generate post.html";
+        write(root.path().join("content/post.en.md"), "Post\n").unwrap();
+        let changes = Changes {
+            content: BTreeSet::from([PathBuf::from("post.en.md")]),
+            ..Default::default()
+        };
+        assert_eq!(builder.update(&changes).unwrap_err().to_string(), duplicate);
+
+        let mut builder = Builder::new(&config, false, false);
+        assert_eq!(builder.build().unwrap_err().to_string(), duplicate);
+    }
+
+    #[test]
+    fn test_template_changes_regenerate_dependent_pages() {
+        let root = write_project(&[
+            ("tars.toml", CONFIG),
+            ("content/post.md", "---\ntemplate: post.html\n---\nPost\n"),
+            (
+                "content/plain.md",
+                "---\ntemplate: plain.html\n---\nPlain\n",
+            ),
+            (
+                "content/dated.md",
+                "---\ntemplate: dated.html\n---\nDated\n",
+            ),
+            (
+                "template/base.html",
+                "<main>{% block body %}{% endblock %}</main>",
+            ),
+            (
+                "template/post.html",
+                "{% extends \"base.html\" %}{% block body %}{%- include \"byline.html\" -%}{{ content }}{% endblock %}",
+            ),
+            ("template/byline.html", "by A"),
+            ("template/plain.html", "{{ content }}"),
+            ("template/dated.html", "{{ content }}{{ now() }}"),
+        ]);
+        let config = load_config(&root);
+        let build_dir = root.path().join("build");
+
+        // Returns the pages generated by a build rather than kept from the
+        // previous one, which are linked, so keep their modification time.
+        let build = || {
+            let pages = ["post.html", "plain.html", "dated.html"];
+            let modified = |page: &str| {
+                std::fs::metadata(build_dir.join(page))
+                    .and_then(|m| m.modified())
+                    .ok()
+            };
+            let before: Vec<_> = pages.iter().map(|page| modified(page)).collect();
+
+            Builder::new(&config, false, false).build().unwrap();
+
+            pages
+                .iter()
+                .zip(before)
+                .filter(|(page, before)| modified(page) != *before)
+                .map(|(page, _)| *page)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(build(), ["post.html", "plain.html", "dated.html"]);
+        assert_eq!(build(), ["dated.html"]);
+
+        write(
+            root.path().join("template/base.html"),
+            "<article>{% block body %}{% endblock %}</article>",
+        )
+        .unwrap();
+        assert_eq!(build(), ["post.html", "dated.html"]);
+
+        write(root.path().join("template/byline.html"), "by B").unwrap();
+        assert_eq!(build(), ["post.html", "dated.html"]);
+
+        let post = std::fs::read_to_string(build_dir.join("post.html")).unwrap();
+        assert!(post.starts_with("<article>by B"), "{post}");
+    }
+}
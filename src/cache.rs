@@ -0,0 +1,189 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+use walkdir::WalkDir;
+
+const CACHE_FILE: &str = "build.json";
+
+/// Inputs of a generated page, as recorded in the build cache.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CachedPage {
+    /// Hash of the page's source file.
+    pub source: String,
+    /// Source hash of each template used to render the page.
+    pub templates: BTreeMap<String, String>,
+    /// Whether the page's templates read other pages.
+    pub pages: bool,
+    /// Whether the page's templates reference static files.
+    pub assets: bool,
+    /// Whether the page's templates load other templates by expression, so
+    /// its dependencies aren't known, or read the current time.
+    pub dynamic: bool,
+}
+
+/// Hashes of the inputs of a build, used to skip pages which are unchanged
+/// since the previous build.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    pub version: String,
+    /// Hash of inputs which affect every page: configuration, data files and
+    /// translations.
+    pub global: String,
+    /// Hash of the path and front matter of every page, which affect menus,
+    /// translations and page listings.
+    pub site: String,
+    /// Hash of every content file.
+    pub content: String,
+    /// Hash of every static file.
+    pub statics: String,
+    /// Generated pages, by path relative to the build directory.
+    pub pages: BTreeMap<PathBuf, CachedPage>,
+    /// Size, modification time and hash of every hashed file.
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, FileStamp>,
+}
+
+/// Size and modification time of a file, and the hash of its contents when
+/// they were last read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub len: u64,
+    pub modified: u128,
+    pub digest: String,
+}
+
+/// Hashes of source files, which are only read again when their size or
+/// modification time changes.
+#[derive(Debug, Default)]
+pub struct FileHashes {
+    stamps: Mutex<HashMap<PathBuf, FileStamp>>,
+}
+
+impl FileHashes {
+    /// Reuse the hashes recorded in a previous build's cache.
+    pub fn extend(&self, files: &BTreeMap<PathBuf, FileStamp>) {
+        let mut stamps = self.stamps.lock().unwrap();
+
+        for (path, stamp) in files {
+            stamps.entry(path.clone()).or_insert_with(|| stamp.clone());
+        }
+    }
+
+    /// Returns the hash of the contents of the file at `path`.
+    pub fn digest(&self, path: &Path) -> Result<String> {
+        let metadata = fs::metadata(path)?;
+        let len = metadata.len();
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+
+        let stamps = self.stamps.lock().unwrap();
+        let unchanged = stamps
+            .get(path)
+            .filter(|stamp| stamp.len == len && stamp.modified == modified);
+        if let Some(stamp) = unchanged {
+            return Ok(stamp.digest.clone());
+        }
+        drop(stamps);
+
+        let digest = sha256::try_digest(path)?;
+        let stamp = FileStamp {
+            len,
+            modified,
+            digest: digest.clone(),
+        };
+        self.stamps
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), stamp);
+
+        Ok(digest)
+    }
+
+    /// Returns the stamps of files which still exist, to save in the cache.
+    pub fn stamps(&self) -> BTreeMap<PathBuf, FileStamp> {
+        let mut stamps = self.stamps.lock().unwrap();
+        stamps.retain(|path, _| path.is_file());
+
+        stamps
+            .iter()
+            .map(|(path, stamp)| (path.clone(), stamp.clone()))
+            .collect()
+    }
+}
+
+impl BuildCache {
+    pub fn new() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Load the cache from `cache_dir`.
+    ///
+    /// Returns `None` if there's no cache, or it was written by another
+    /// version of tars.
+    pub fn load(cache_dir: &Path) -> Option<Self> {
+        let cache = fs::read_to_string(cache_dir.join(CACHE_FILE)).ok()?;
+        let cache: Self = serde_json::from_str(&cache).ok()?;
+
+        (cache.version == env!("CARGO_PKG_VERSION")).then_some(cache)
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        fs::create_dir_all(cache_dir)?;
+        fs::write(cache_dir.join(CACHE_FILE), serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    /// Returns whether the page at `rel_path` must be rendered again, given
+    /// the cache from the previous build.
+    pub fn is_stale(&self, prev: &BuildCache, rel_path: &Path, page: &CachedPage) -> bool {
+        let Some(prev_page) = prev.pages.get(rel_path) else {
+            return true;
+        };
+
+        if page.dynamic || self.global != prev.global || page != prev_page {
+            return true;
+        }
+
+        // Pages without templates only depend on their source.
+        if page.templates.is_empty() {
+            return false;
+        }
+
+        self.site != prev.site
+            || (page.pages && self.content != prev.content)
+            || (page.assets && self.statics != prev.statics)
+    }
+}
+
+/// Returns a hash of the paths and contents of every file in `dirs`.
+///
+/// Directories which don't exist are skipped.
+pub fn hash_dirs(dirs: &[&Path], hashes: &FileHashes) -> Result<String> {
+    let mut listing = String::new();
+
+    for (i, dir) in dirs.iter().enumerate().filter(|(_, dir)| dir.is_dir()) {
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry?;
+
+            if entry.file_type().is_file() {
+                let rel_path = entry.path().strip_prefix(dir)?;
+                let digest = hashes.digest(entry.path())?;
+                listing.push_str(&format!("{i}:{}:{digest}\n", rel_path.display()));
+            }
+        }
+    }
+
+    Ok(sha256::digest(listing))
+}
@@ -3,10 +3,13 @@ use serde::{Deserialize, Serialize};
 use sha256::try_digest;
 use std::{
     collections::HashMap,
+    fmt,
     path::{Path, PathBuf},
 };
 
-#[derive(Debug, Deserialize, Default)]
+use crate::validate::{missing_dirs, unknown_keys};
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Config {
     #[serde(default)]
     pub site: Site,
@@ -16,14 +19,37 @@ pub struct Config {
     pub serve: Serve,
     #[serde(default)]
     pub extra: HashMap<String, toml::Value>,
+    pub theme: Option<String>,
     #[serde(default)]
     #[serde(rename = "plugin")]
     pub plugins: Vec<Plugin>,
+    #[serde(default)]
+    #[serde(rename = "menu")]
+    pub menus: HashMap<String, Vec<MenuEntry>>,
+    #[serde(default)]
+    #[serde(rename = "language")]
+    pub languages: Vec<Language>,
     #[serde(default = "default_config_file")]
+    #[serde(skip_serializing)]
     pub path: String,
+    #[serde(skip)]
+    pub root: PathBuf,
+    #[serde(skip)]
+    pub env: Option<String>,
+    #[serde(skip)]
+    pub warnings: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Options controlling how the configuration file is loaded.
+#[derive(Debug, Default, Clone)]
+pub struct LoadOptions {
+    pub root: Option<PathBuf>,
+    pub env: Option<String>,
+    /// Values for dotted keys, e.g. `build.include_drafts`, applied last.
+    pub overrides: Vec<(String, toml::Value)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Site {
     pub title: Option<String>,
     pub base_url: Option<String>,
@@ -33,6 +59,39 @@ pub struct Site {
     pub default_template: String,
 }
 
+/// Resolved directories sources are read from during a build.
+///
+/// Template and static directories are listed in order of precedence, with
+/// the project's own directory first, followed by any theme directory.
+#[derive(Debug, Clone, Default)]
+pub struct Roots {
+    pub content: PathBuf,
+    pub templates: Vec<PathBuf>,
+    pub statics: Vec<PathBuf>,
+    pub data: PathBuf,
+    pub i18n: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteOverride {
+    pub title: Option<String>,
+    pub base_url: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub default_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Language {
+    pub code: String,
+    pub title: Option<String>,
+    pub base_path: Option<String>,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub site: SiteOverride,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Build {
     #[serde(default = "default_content_dir")]
@@ -45,12 +104,27 @@ pub struct Build {
     pub build_dir: String,
     #[serde(default = "default_plugin_dir")]
     pub plugin_dir: String,
+    #[serde(default = "default_theme_dir")]
+    pub theme_dir: String,
+    #[serde(default = "default_archetype_dir")]
+    pub archetype_dir: String,
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    #[serde(default = "default_i18n_dir")]
+    pub i18n_dir: String,
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: String,
+    #[serde(default)]
+    pub i18n_strict: bool,
     #[serde(default)]
     pub include_drafts: bool,
     #[serde(default = "default_static_prefix")]
     pub static_prefix: String,
     #[serde(default)]
     pub no_verify: bool,
+    /// Number of threads used to load and render pages, or 0 for one per CPU.
+    #[serde(default)]
+    pub jobs: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,6 +135,25 @@ pub struct Serve {
     pub port: u16,
     #[serde(default = "default_serve_reload")]
     pub auto_reload: bool,
+    /// Time to wait for further file changes before rebuilding.
+    #[serde(default = "default_serve_debounce_ms")]
+    pub debounce_ms: u64,
+    /// Glob patterns for changed files to ignore, matched against file names
+    /// and paths relative to the project root.
+    #[serde(default = "default_serve_ignore")]
+    pub ignore: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MenuEntry {
+    pub name: String,
+    pub url: Option<String>,
+    pub page: Option<String>,
+    #[serde(default)]
+    pub weight: i64,
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub children: Vec<MenuEntry>,
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
@@ -130,7 +223,8 @@ impl Plugin {
     pub fn run(&self, config: &Config, root_dir: &Path, no_verify: bool) -> Result<()> {
         println!("Running plugin {}...", &self.name);
 
-        let plugin_file = self.resolve(Path::new(&config.build.plugin_dir), no_verify)?;
+        let plugin_file =
+            self.resolve(&config.project_path(&config.build.plugin_dir), no_verify)?;
         let args = self.get_args();
 
         let status = std::process::Command::new(&plugin_file)
@@ -153,6 +247,19 @@ impl Plugin {
     }
 }
 
+impl Language {
+    /// Output path prefix for this language.
+    ///
+    /// Defaults to the language code, or the site root for the default language.
+    pub fn base_path(&self) -> String {
+        match &self.base_path {
+            Some(path) => path.trim_matches('/').to_string(),
+            None if self.default => String::new(),
+            None => self.code.clone(),
+        }
+    }
+}
+
 impl Default for Site {
     fn default() -> Self {
         Self {
@@ -173,9 +280,16 @@ impl Default for Build {
             static_dir: default_static_dir(),
             build_dir: default_build_dir(),
             plugin_dir: default_plugin_dir(),
+            theme_dir: default_theme_dir(),
+            archetype_dir: default_archetype_dir(),
+            data_dir: default_data_dir(),
+            i18n_dir: default_i18n_dir(),
+            cache_dir: default_cache_dir(),
+            i18n_strict: false,
             include_drafts: false,
             static_prefix: default_static_prefix(),
             no_verify: false,
+            jobs: 0,
         }
     }
 }
@@ -186,6 +300,8 @@ impl Default for Serve {
             host: default_serve_host(),
             port: default_serve_port(),
             auto_reload: default_serve_reload(),
+            debounce_ms: default_serve_debounce_ms(),
+            ignore: default_serve_ignore(),
         }
     }
 }
@@ -211,6 +327,21 @@ fn default_build_dir() -> String {
 fn default_plugin_dir() -> String {
     "plugin".to_string()
 }
+fn default_theme_dir() -> String {
+    "themes".to_string()
+}
+fn default_archetype_dir() -> String {
+    "archetypes".to_string()
+}
+fn default_data_dir() -> String {
+    "data".to_string()
+}
+fn default_i18n_dir() -> String {
+    "i18n".to_string()
+}
+fn default_cache_dir() -> String {
+    ".tars-cache".to_string()
+}
 fn default_static_prefix() -> String {
     "static".to_string()
 }
@@ -223,13 +354,644 @@ fn default_serve_port() -> u16 {
 fn default_serve_reload() -> bool {
     false
 }
+fn default_serve_debounce_ms() -> u64 {
+    100
+}
+fn default_serve_ignore() -> Vec<String> {
+    // Editor swap, backup and lock files.
+    [
+        "*.swp",
+        "*.swx",
+        "*~",
+        ".#*",
+        "#*#",
+        "4913",
+        "*.tmp",
+        ".DS_Store",
+    ]
+    .map(String::from)
+    .to_vec()
+}
 
 impl Config {
-    pub fn from_file(path: &str) -> Result<Self> {
-        let data = std::fs::read_to_string(path)?;
-        let mut cfg: Self = toml::from_str(&data)?;
+    /// Returns the source directories of the project.
+    pub fn roots(&self) -> Roots {
+        let mut templates = vec![self.project_path(&self.build.template_dir)];
+        let mut statics = vec![self.project_path(&self.build.static_dir)];
+
+        if let Some(theme_root) = self.theme_root() {
+            templates.push(theme_root.join(default_template_dir()));
+            statics.push(theme_root.join(default_static_dir()));
+        }
+
+        Roots {
+            content: self.project_path(&self.build.content_dir),
+            templates,
+            statics,
+            data: self.project_path(&self.build.data_dir),
+            i18n: self.project_path(&self.build.i18n_dir),
+        }
+    }
+
+    /// Returns the root directory of the configured theme, if any.
+    pub fn theme_root(&self) -> Option<PathBuf> {
+        self.theme
+            .as_ref()
+            .map(|theme| self.project_path(&self.build.theme_dir).join(theme))
+    }
+
+    /// Returns the files the configuration is loaded from, including the
+    /// selected environment's file and the theme's configuration, which may
+    /// not exist.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let path = Path::new(&self.path);
+        let mut files = vec![path.to_path_buf()];
+
+        if let Some(env) = &self.env {
+            files.push(env_config_path(path, env));
+        }
+        if let Some(theme_root) = self.theme_root() {
+            files.push(theme_root.join("tars.toml"));
+        }
+
+        files
+    }
+
+    /// Returns the default language, if any languages are configured.
+    pub fn default_language(&self) -> Option<&Language> {
+        self.languages.iter().find(|l| l.default)
+    }
+
+    /// Returns the configured language with the given code.
+    pub fn language(&self, code: &str) -> Option<&Language> {
+        self.languages.iter().find(|l| l.code == code)
+    }
+
+    /// Returns site settings with any overrides for `lang` applied.
+    pub fn site_for(&self, lang: &Language) -> Site {
+        let site = &self.site;
+        let over = &lang.site;
+
+        Site {
+            title: over.title.clone().or(site.title.clone()),
+            base_url: over.base_url.clone().or(site.base_url.clone()),
+            author: over.author.clone().or(site.author.clone()),
+            description: over.description.clone().or(site.description.clone()),
+            default_template: over
+                .default_template
+                .clone()
+                .unwrap_or(site.default_template.clone()),
+        }
+    }
+
+    /// Resolve a path from the configuration against the project root.
+    pub fn project_path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Returns the effective configuration as TOML.
+    pub fn to_toml(&self) -> Result<String> {
+        // Convert to a table first, so keys are sorted.
+        let table = toml::Table::try_from(self)?;
+        Ok(toml::to_string_pretty(&table)?)
+    }
+
+    /// Load the configuration file at `path`.
+    ///
+    /// Paths in the configuration are relative to `options.root` if given,
+    /// otherwise to the directory containing the configuration file.
+    ///
+    /// If an environment is selected, with `options.env` or `TARS_ENV`, the
+    /// `[env.<name>]` table and `<config>.<name>.toml` file are merged over
+    /// the base configuration. `TARS_`-prefixed environment variables are
+    /// applied last, using `__` to separate keys, e.g. `TARS_SITE__BASE_URL`.
+    ///
+    /// Override values given as strings are converted to the type of the
+    /// setting they replace.
+    pub fn from_file(path: &str, options: &LoadOptions) -> Result<Self> {
+        let root = match &options.root {
+            Some(root) => root.clone(),
+            None => Path::new(path)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        };
+
+        let mut table = read_table(Path::new(path))?;
+
+        let env = options
+            .env
+            .clone()
+            .or_else(|| std::env::var("TARS_ENV").ok());
+        let env_tables = table.remove("env");
+
+        if let Some(env) = &env {
+            let mut found = false;
+
+            if let Some(env_table) = env_tables
+                .as_ref()
+                .and_then(|t| t.get(env))
+                .and_then(|t| t.as_table())
+            {
+                merge_tables(&mut table, env_table.clone());
+                found = true;
+            }
+
+            let env_file = env_config_path(Path::new(path), env);
+            if env_file.is_file() {
+                merge_tables(&mut table, read_table(&env_file)?);
+                found = true;
+            }
+
+            if !found {
+                return Err(anyhow!(
+                    "environment '{}' not found, expected [env.{}] or {}",
+                    env,
+                    env,
+                    env_file.display()
+                ));
+            }
+        }
+
+        let schema = override_schema()?;
+        let (env_overrides, env_warnings) = env_var_overrides(std::env::vars(), &schema);
+
+        for (key, value) in env_overrides.into_iter().chain(options.overrides.clone()) {
+            let keys: Vec<&str> = key.split('.').collect();
+            let target = lookup_key(&table, &keys).or_else(|| lookup_key(&schema, &keys));
+            let value = coerce_override(&key, value, target)?;
+            merge_tables(&mut table, override_table(&keys, value));
+        }
+
+        let mut warnings = env_warnings;
+
+        // Theme configuration provides defaults for the project configuration.
+        if let Some(theme) = table.get("theme").and_then(|t| t.as_str()) {
+            let theme_dir = table
+                .get("build")
+                .and_then(|b| b.get("theme_dir"))
+                .and_then(|d| d.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(default_theme_dir);
+            let theme_root = root.join(&theme_dir).join(theme);
+            let theme_config = theme_root.join("tars.toml");
+
+            if !theme_root.is_dir() {
+                return Err(anyhow!(
+                    "theme '{}' not found in {}",
+                    theme,
+                    root.join(&theme_dir).display()
+                ));
+            }
+
+            if theme_config.is_file() {
+                let mut theme_table = read_table(&theme_config)?;
+
+                theme_table.retain(|key, _| {
+                    let allowed = THEME_SECTIONS.contains(&key);
+                    if !allowed {
+                        warnings.push(format!(
+                            "ignoring '{key}' in theme configuration {}, themes may only set {}",
+                            theme_config.display(),
+                            THEME_SECTIONS.join(", ")
+                        ));
+                    }
+                    allowed
+                });
+
+                merge_tables(&mut theme_table, table);
+                table = theme_table;
+            }
+        }
+
+        warnings.extend(unknown_keys(&table));
+
+        let mut cfg: Self = table.clone().try_into()?;
         cfg.path = path.to_string();
+        cfg.root = root;
+        cfg.env = env;
+        cfg.warnings = warnings;
+        cfg.warnings.extend(missing_dirs(&cfg, &table));
+
+        // If no language is marked as the default, the first declared one is.
+        match cfg.languages.iter().filter(|l| l.default).count() {
+            0 => {
+                if let Some(lang) = cfg.languages.first_mut() {
+                    lang.default = true;
+                }
+            }
+            1 => {}
+            _ => return Err(anyhow!("only one language may be marked as default")),
+        }
 
         Ok(cfg)
     }
 }
+
+/// Sections a theme's configuration may provide defaults for.
+///
+/// Others are only set by the project, since they choose where files are
+/// written and deleted, or commands to run, such as `build` and `plugin`.
+const THEME_SECTIONS: &[&str] = &["site", "extra", "menu", "language"];
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let data = std::fs::read_to_string(path)?;
+
+    toml::from_str(&data).map_err(|source| {
+        let line = source
+            .span()
+            .map(|span| data[..span.start].matches('\n').count() + 1);
+
+        ParseError {
+            path: path.to_path_buf(),
+            line,
+            source,
+        }
+        .into()
+    })
+}
+
+/// A configuration file which isn't valid TOML.
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: PathBuf,
+    /// Line of the error, starting from 1.
+    pub line: Option<usize>,
+    source: toml::de::Error,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.source)
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Returns the path of the configuration file for `env`, e.g. `tars.production.toml`.
+fn env_config_path(path: &Path, env: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "tars".to_string());
+
+    path.with_file_name(format!("{stem}.{env}.toml"))
+}
+
+/// Parse an override value for a setting of unknown type as TOML, falling
+/// back to a plain string.
+fn parse_override_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+/// Parse a `key=value` override, e.g. `build.include_drafts=true`.
+///
+/// The value is kept as a string, which is converted to the setting's type
+/// when the configuration is loaded.
+pub fn parse_override(arg: &str) -> Result<(String, toml::Value)> {
+    let (key, raw) = arg
+        .split_once('=')
+        .ok_or_else(|| anyhow!("override '{}' must be in the form KEY=VALUE", arg))?;
+    let key = key.trim();
+
+    if key.is_empty() || key.split('.').any(str::is_empty) {
+        return Err(anyhow!("invalid key in override '{}'", arg));
+    }
+
+    Ok((key.to_string(), toml::Value::String(raw.trim().to_string())))
+}
+
+/// Build a table setting the dotted key path `keys` to `value`.
+fn override_table(keys: &[&str], value: toml::Value) -> toml::Table {
+    let mut value = value;
+    for key in keys[1..].iter().rev() {
+        let mut table = toml::Table::new();
+        table.insert(key.to_string(), value);
+        value = toml::Value::Table(table);
+    }
+
+    let mut table = toml::Table::new();
+    table.insert(keys[0].to_string(), value);
+    table
+}
+
+/// Returns a configuration table with every optional setting set, which
+/// gives the type of each setting that overrides are converted to.
+fn override_schema() -> Result<toml::Table> {
+    let config = Config {
+        site: Site {
+            title: Some(String::new()),
+            base_url: Some(String::new()),
+            author: Some(String::new()),
+            description: Some(String::new()),
+            ..Default::default()
+        },
+        theme: Some(String::new()),
+        ..Default::default()
+    };
+
+    Ok(toml::Table::try_from(config)?)
+}
+
+/// Returns the value at the dotted key path `keys` in `table`, if any.
+fn lookup_key<'a>(table: &'a toml::Table, keys: &[&str]) -> Option<&'a toml::Value> {
+    let (last, parents) = keys.split_last()?;
+    let mut table = table;
+    for key in parents {
+        table = table.get(*key)?.as_table()?;
+    }
+
+    table.get(*last)
+}
+
+/// Convert an override given as a string to the type of the `target` value
+/// it replaces, or parse it as TOML if the setting's type isn't known.
+///
+/// Values given with a type, such as by `--drafts`, are used as they are.
+fn coerce_override(
+    key: &str,
+    value: toml::Value,
+    target: Option<&toml::Value>,
+) -> Result<toml::Value> {
+    let toml::Value::String(raw) = value else {
+        return Ok(value);
+    };
+    let invalid = |expected: &str| {
+        anyhow!(
+            "invalid value '{}' for '{}', expected {}",
+            raw,
+            key,
+            expected
+        )
+    };
+
+    Ok(match target {
+        Some(toml::Value::Integer(_)) => {
+            toml::Value::Integer(raw.parse().map_err(|_| invalid("an integer"))?)
+        }
+        Some(toml::Value::Float(_)) => {
+            toml::Value::Float(raw.parse().map_err(|_| invalid("a number"))?)
+        }
+        Some(toml::Value::Boolean(_)) => {
+            toml::Value::Boolean(raw.parse().map_err(|_| invalid("true or false"))?)
+        }
+        Some(toml::Value::Datetime(_)) => {
+            toml::Value::Datetime(raw.parse().map_err(|_| invalid("a date"))?)
+        }
+        Some(toml::Value::String(_)) => toml::Value::String(raw),
+        Some(toml::Value::Array(_) | toml::Value::Table(_)) | None => parse_override_value(&raw),
+    })
+}
+
+/// Collect configuration overrides from environment variables named
+/// `TARS_<SECTION>__<KEY>`, e.g. `TARS_BUILD__INCLUDE_DRAFTS`.
+///
+/// Returns the overrides as dotted keys and string values, along with
+/// warnings for variables naming settings which don't exist. Variables
+/// without a `__` separator, such as `TARS_ENV`, aren't overrides.
+fn env_var_overrides(
+    vars: impl Iterator<Item = (String, String)>,
+    schema: &toml::Table,
+) -> (Vec<(String, toml::Value)>, Vec<String>) {
+    let mut overrides = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix("TARS_") else {
+            continue;
+        };
+        if !key.contains("__") {
+            continue;
+        }
+
+        let key = key.to_lowercase();
+        let keys: Vec<&str> = key.split("__").collect();
+
+        // `[extra]` is free-form, other settings must exist.
+        let known = match keys.as_slice() {
+            ["extra", rest @ ..] => !rest.is_empty() && rest.iter().all(|k| !k.is_empty()),
+            _ => lookup_key(schema, &keys).is_some_and(|v| !v.is_table()),
+        };
+
+        if !known {
+            warnings.push(format!(
+                "ignoring environment variable {name}: unknown setting '{}'",
+                keys.join(".")
+            ));
+            continue;
+        }
+
+        overrides.push((keys.join("."), toml::Value::String(raw)));
+    }
+
+    overrides.sort_by(|a, b| a.0.cmp(&b.0));
+
+    (overrides, warnings)
+}
+
+/// Recursively merge `over` into `base`, with values in `over` taking precedence.
+pub fn merge_tables(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(over_table)) => {
+                merge_tables(base_table, over_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn load(config: &str, overrides: &[(&str, toml::Value)]) -> Result<Config> {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tars.toml");
+        fs::write(&path, config).unwrap();
+
+        let options = LoadOptions {
+            overrides: overrides
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+            ..Default::default()
+        };
+
+        Config::from_file(&path.to_string_lossy(), &options)
+    }
+
+    #[test]
+    fn test_project_paths() {
+        let dir = tempdir().unwrap();
+        let site = dir.path().join("site");
+        fs::create_dir_all(site.join("themes/plain")).unwrap();
+        let path = site.join("tars.toml");
+        fs::write(&path, "theme = \"plain\"\n").unwrap();
+        let path = path.to_string_lossy();
+
+        let config = Config::from_file(&path, &LoadOptions::default()).unwrap();
+        let roots = config.roots();
+        assert_eq!(roots.content, site.join("content"));
+        assert_eq!(
+            roots.templates,
+            [site.join("template"), site.join("themes/plain/template")]
+        );
+
+        let other = dir.path().join("other");
+        let options = LoadOptions {
+            root: Some(other.clone()),
+            ..Default::default()
+        };
+        let err = Config::from_file(&path, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "theme 'plain' not found in {}",
+                other.join("themes").display()
+            )
+        );
+
+        fs::create_dir_all(other.join("themes/plain")).unwrap();
+        let config = Config::from_file(&path, &options).unwrap();
+        assert_eq!(config.roots().content, other.join("content"));
+        assert_eq!(config.theme_root(), Some(other.join("themes/plain")));
+    }
+
+    #[test]
+    fn test_theme_sections() {
+        let dir = tempdir().unwrap();
+        let theme_dir = dir.path().join("themes/plain");
+        fs::create_dir_all(&theme_dir).unwrap();
+        fs::write(
+            theme_dir.join("tars.toml"),
+            r#"theme = "other"
+
+[site]
+title = "Theme title"
+author = "Theme author"
+
+[extra]
+accent = "blue"
+
+[build]
+build_dir = "/"
+content_dir = "../elsewhere"
+
+[[plugin]]
+hook = "pre"
+name = "rm -rf ~"
+hash = ""
+"#,
+        )
+        .unwrap();
+
+        let path = dir.path().join("tars.toml");
+        fs::write(&path, "theme = \"plain\"\n\n[site]\ntitle = \"Blog\"\n").unwrap();
+        let config = Config::from_file(&path.to_string_lossy(), &LoadOptions::default()).unwrap();
+
+        assert_eq!(config.theme.as_deref(), Some("plain"));
+        assert_eq!(config.site.title.as_deref(), Some("Blog"));
+        assert_eq!(config.site.author.as_deref(), Some("Theme author"));
+        assert_eq!(config.extra["accent"].as_str(), Some("blue"));
+        assert_eq!(config.build.build_dir, Build::default().build_dir);
+        assert_eq!(config.build.content_dir, Build::default().content_dir);
+        assert!(config.plugins.is_empty());
+
+        let ignored: Vec<_> = config
+            .warnings
+            .iter()
+            .filter(|w| w.starts_with("ignoring '"))
+            .map(|w| w.split('\'').nth(1).unwrap())
+            .collect();
+        assert_eq!(ignored, ["build", "plugin", "theme"]);
+    }
+
+    fn raw(value: &str) -> toml::Value {
+        toml::Value::String(value.to_string())
+    }
+
+    #[test]
+    fn test_overrides_keep_string_types() {
+        let config = load(
+            "[site]\ntitle = \"Blog\"\n",
+            &[("site.title", raw("1984")), ("site.author", raw("true"))],
+        )
+        .unwrap();
+
+        assert_eq!(config.site.title.as_deref(), Some("1984"));
+        assert_eq!(config.site.author.as_deref(), Some("true"));
+
+        // Settings missing from the file are typed by the configuration.
+        let config = load(
+            "",
+            &[
+                ("site.title", raw("2024-01-01")),
+                ("build.data_dir", raw("1.5")),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.site.title.as_deref(), Some("2024-01-01"));
+        assert_eq!(config.build.data_dir, "1.5");
+    }
+
+    #[test]
+    fn test_overrides_convert_to_setting_type() {
+        let config = load(
+            "",
+            &[
+                ("build.jobs", raw("4")),
+                ("build.include_drafts", raw("true")),
+                ("serve.ignore", raw("[\"*.bak\"]")),
+                ("extra.count", raw("3")),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.build.jobs, 4);
+        assert!(config.build.include_drafts);
+        assert_eq!(config.serve.ignore, ["*.bak"]);
+        assert_eq!(config.extra["count"].as_integer(), Some(3));
+
+        let err = load("", &[("build.jobs", raw("many"))]).unwrap_err();
+        assert!(err.to_string().contains("build.jobs"));
+    }
+
+    #[test]
+    fn test_env_var_overrides() {
+        let schema = override_schema().unwrap();
+        let vars = [
+            ("TARS_HOME", "/opt/tars"),
+            ("TARS_ENV", "production"),
+            ("TARS_SITE__TITLE", "1984"),
+            ("TARS_EXTRA__ANALYTICS__ID", "42"),
+            ("TARS_SITE__TYPO", "x"),
+            ("HOME", "/root"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        let (overrides, warnings) = env_var_overrides(vars.into_iter(), &schema);
+
+        assert_eq!(
+            overrides,
+            [
+                ("extra.analytics.id".to_string(), raw("42")),
+                ("site.title".to_string(), raw("1984")),
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("TARS_SITE__TYPO"));
+    }
+}
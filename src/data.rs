@@ -0,0 +1,151 @@
+use anyhow::{Result, anyhow};
+use serde_json::{Map, Value};
+use std::{fs, path::Path};
+use walkdir::WalkDir;
+
+/// Load every supported data file below `data_root` into a nested map.
+///
+/// Files are keyed by their path relative to `data_root`, without the
+/// extension, so `data/team/members.yaml` becomes `team.members`.
+/// If the data directory doesn't exist, an empty map is returned.
+pub fn load_data_dir(data_root: &Path) -> Result<Value> {
+    let mut root = Map::new();
+
+    if !data_root.is_dir() {
+        return Ok(Value::Object(root));
+    }
+
+    for entry in WalkDir::new(data_root).sort_by_file_name() {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Some(value) = load_data_file(path)? else {
+            continue;
+        };
+
+        let rel_path = path.strip_prefix(data_root)?.with_extension("");
+        let mut keys: Vec<String> = rel_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let last = keys
+            .pop()
+            .ok_or_else(|| anyhow!("invalid data file path {}", path.display()))?;
+
+        let mut node = &mut root;
+        for key in keys {
+            let child = node.entry(key).or_insert_with(|| Value::Object(Map::new()));
+
+            node = child
+                .as_object_mut()
+                .ok_or_else(|| anyhow!("data key conflict while loading {}", path.display()))?;
+        }
+
+        if node.contains_key(&last) {
+            return Err(anyhow!(
+                "data key conflict while loading {}",
+                path.display()
+            ));
+        }
+
+        node.insert(last, value);
+
+        println!("Loaded data file {}", rel_path.display());
+    }
+
+    Ok(Value::Object(root))
+}
+
+/// Parse a single data file based on its extension.
+///
+/// Returns `None` for files with unsupported extensions.
+pub fn load_data_file(path: &Path) -> Result<Option<Value>> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let value = match ext.as_str() {
+        "json" => serde_json::from_str(&fs::read_to_string(path)?)?,
+        "yaml" | "yml" => serde_yaml::from_str(&fs::read_to_string(path)?)?,
+        "toml" => toml::from_str(&fs::read_to_string(path)?)?,
+        "csv" => parse_csv(&fs::read_to_string(path)?)?,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(value))
+}
+
+/// Parse CSV data into a list of objects keyed by the header row.
+fn parse_csv(data: &str) -> Result<Value> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut rows = Vec::new();
+
+    for record in reader.records() {
+        let record = record?;
+        let row: Map<String, Value> = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect();
+
+        rows.push(Value::Object(row));
+    }
+
+    Ok(Value::Array(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_data_dir() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("team")).unwrap();
+        fs::write(dir.path().join("site.json"), r#"{"name": "Blog"}"#).unwrap();
+        fs::write(dir.path().join("links.toml"), "home = \"/\"\n").unwrap();
+        fs::write(dir.path().join("team/members.yaml"), "- name: A\n").unwrap();
+        fs::write(dir.path().join("team/roles.csv"), "name,role\nA,editor\n").unwrap();
+        fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        assert_eq!(
+            load_data_dir(dir.path()).unwrap(),
+            json!({
+                "site": { "name": "Blog" },
+                "links": { "home": "/" },
+                "team": {
+                    "members": [{ "name": "A" }],
+                    "roles": [{ "name": "A", "role": "editor" }],
+                },
+            })
+        );
+
+        assert_eq!(
+            load_data_dir(&dir.path().join("missing")).unwrap(),
+            json!({})
+        );
+    }
+
+    #[test]
+    fn test_load_data_dir_errors() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("team")).unwrap();
+        fs::write(dir.path().join("team/members.json"), "[]").unwrap();
+        fs::write(dir.path().join("team.json"), "{}").unwrap();
+
+        let err = load_data_dir(dir.path()).unwrap_err();
+        assert!(err.to_string().starts_with("data key conflict"), "{err}");
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("broken.json"), "{").unwrap();
+        assert!(load_data_dir(dir.path()).is_err());
+    }
+}
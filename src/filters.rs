@@ -0,0 +1,566 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use minijinja::{
+    Environment, Error, ErrorKind, State,
+    value::{Kwargs, Value},
+};
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use std::path::{Component, Path, PathBuf};
+
+use crate::{
+    config::{Config, Roots},
+    data::load_data_file,
+    markdown::render_markdown,
+};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_WORDS_PER_MINUTE: usize = 200;
+
+/// Characters encoded in URL paths, which would end the path or are unsafe in
+/// HTML attributes.
+const URL_PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Characters encoded in the file names of static assets.
+const URL_SEGMENT: &AsciiSet = &URL_PATH.add(b'%').add(b'/');
+
+/// Register the built-in filters and functions with a template environment.
+pub fn register(env: &mut Environment<'_>, config: &Config, roots: &Roots) {
+    env.add_filter("markdown", markdown);
+    env.add_filter("slugify", slugify);
+    env.add_filter("truncate_words", truncate_words);
+    env.add_filter("json_encode", json_encode);
+    env.add_filter("date", date);
+    env.add_filter("reading_time", reading_time);
+
+    env.add_function("get_page", get_page);
+    env.add_function("get_section", get_section);
+    env.add_function("now", now);
+
+    let data_root = roots.data.clone();
+    env.add_function("load_data", move |path: &str| load_data(&data_root, path));
+
+    let static_dirs = roots.statics.clone();
+    let static_prefix = config.build.static_prefix.clone();
+    let base_url = config.site.base_url.clone();
+    env.add_function("asset_url", move |path: &str| {
+        asset_url(&static_dirs, &static_prefix, base_url.as_deref(), path)
+    });
+}
+
+fn invalid(detail: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidOperation, detail.into())
+}
+
+/// Render a Markdown string to HTML.
+fn markdown(value: &str) -> Value {
+    Value::from_safe_string(render_markdown(value))
+}
+
+/// Convert a string into a lowercase, hyphen-separated slug.
+pub fn slugify(value: &str) -> String {
+    let mut slug = String::with_capacity(value.len());
+
+    for c in value.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Truncate a string to `count` words, appending `end` if anything was removed.
+fn truncate_words(value: &str, count: Option<usize>, kwargs: Kwargs) -> Result<String, Error> {
+    let count = match count {
+        Some(count) => count,
+        None => kwargs.get::<Option<usize>>("count")?.unwrap_or(50),
+    };
+    let end = kwargs.get::<Option<&str>>("end")?.unwrap_or("…");
+    kwargs.assert_all_used()?;
+
+    let words: Vec<&str> = value.split_whitespace().collect();
+    if words.len() <= count {
+        return Ok(words.join(" "));
+    }
+
+    Ok(format!("{}{}", words[..count].join(" "), end))
+}
+
+/// Serialize a value as JSON, optionally pretty-printed.
+///
+/// HTML special characters are escaped as unicode escapes, so the output is
+/// safe to embed in a `<script>` element or an attribute.
+fn json_encode(value: Value, kwargs: Kwargs) -> Result<Value, Error> {
+    let pretty = kwargs.get::<Option<bool>>("pretty")?.unwrap_or(false);
+    kwargs.assert_all_used()?;
+
+    let json = if pretty {
+        serde_json::to_string_pretty(&value)
+    } else {
+        serde_json::to_string(&value)
+    }
+    .map_err(|e| invalid(format!("cannot encode value as JSON: {e}")))?;
+
+    // These characters only appear within JSON strings, where the escapes
+    // are equivalent.
+    let json = json
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+        .replace('\'', "\\u0027");
+
+    Ok(Value::from_safe_string(json))
+}
+
+/// Format a date, datetime, or UNIX timestamp using a `strftime`-style format.
+fn date(value: Value, format: Option<&str>) -> Result<String, Error> {
+    let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+
+    if let Ok(timestamp) = i64::try_from(value.clone()) {
+        let dt = DateTime::from_timestamp(timestamp, 0)
+            .ok_or_else(|| invalid(format!("invalid timestamp {timestamp}")))?;
+        return Ok(dt.format(format).to_string());
+    }
+
+    let s = value
+        .as_str()
+        .ok_or_else(|| invalid(format!("cannot format {} as a date", value.kind())))?;
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        Ok(dt.format(format).to_string())
+    } else if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
+        Ok(dt.format(format).to_string())
+    } else if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(d.format(format).to_string())
+    } else {
+        Err(invalid(format!("cannot parse '{s}' as a date")))
+    }
+}
+
+/// Estimate the reading time of HTML or text content, in whole minutes.
+fn reading_time(value: &str, words_per_minute: Option<usize>) -> usize {
+    let wpm = words_per_minute.unwrap_or(DEFAULT_WORDS_PER_MINUTE).max(1);
+
+    // Strip HTML tags, so markup isn't counted as words.
+    let mut text = String::with_capacity(value.len());
+    let mut in_tag = false;
+    for c in value.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let words = text.split_whitespace().count();
+    words.div_ceil(wpm).max(1)
+}
+
+/// Returns the output path of a content file, e.g. `blog/post.md` becomes
+/// `blog/post.html`.
+fn output_path(path: &str) -> PathBuf {
+    Path::new(path.trim_start_matches('/')).with_extension("html")
+}
+
+fn context_pages(state: &State) -> Result<Vec<Value>, Error> {
+    let pages = state
+        .lookup("pages")
+        .ok_or_else(|| invalid("no pages available in this context"))?;
+
+    Ok(pages.try_iter()?.collect())
+}
+
+fn page_path(page: &Value) -> Option<PathBuf> {
+    page.get_attr("path")
+        .ok()
+        .and_then(|p| p.as_str().map(PathBuf::from))
+}
+
+/// Look up a page by its content path.
+fn get_page(state: &State, path: &str) -> Result<Value, Error> {
+    let target = output_path(path);
+
+    context_pages(state)?
+        .into_iter()
+        .find(|p| page_path(p).as_deref() == Some(target.as_path()))
+        .ok_or_else(|| invalid(format!("page '{path}' not found")))
+}
+
+/// Look up a section by its content directory.
+///
+/// Returns the section `path`, its `index` page if any, and the other
+/// `pages` contained in the section and its subdirectories.
+fn get_section(state: &State, path: &str) -> Result<Value, Error> {
+    let section = Path::new(path.trim_matches('/'));
+    let index_path = section.join("index.html");
+
+    let mut index = Value::from(());
+    let mut pages = Vec::new();
+
+    for page in context_pages(state)? {
+        let Some(page_path) = page_path(&page) else {
+            continue;
+        };
+
+        if page_path == index_path {
+            index = page;
+        } else if page_path.starts_with(section) {
+            pages.push(page);
+        }
+    }
+
+    if index.is_none() && pages.is_empty() {
+        return Err(invalid(format!("section '{path}' not found")));
+    }
+
+    Ok(minijinja::context! {
+        path => section.to_string_lossy(),
+        index => index,
+        pages => pages,
+    })
+}
+
+/// Load a data file relative to the data directory.
+///
+/// Paths outside the data directory are rejected.
+fn load_data(data_root: &Path, path: &str) -> Result<Value, Error> {
+    let full_path = data_root
+        .join(path)
+        .canonicalize()
+        .map_err(|e| invalid(format!("failed to load data file '{path}': {e}")))?;
+    let data_root = data_root
+        .canonicalize()
+        .map_err(|e| invalid(format!("failed to load data file '{path}': {e}")))?;
+
+    if !full_path.starts_with(&data_root) {
+        return Err(invalid(format!(
+            "data file '{path}' is outside the data directory"
+        )));
+    }
+
+    match load_data_file(&full_path) {
+        Ok(Some(value)) => Ok(Value::from_serialize(value)),
+        Ok(None) => Err(invalid(format!("unsupported data file '{path}'"))),
+        Err(e) => Err(invalid(format!("failed to load data file '{path}': {e}"))),
+    }
+}
+
+/// Returns the current local time as an RFC 3339 string.
+fn now() -> String {
+    Local::now().to_rfc3339()
+}
+
+/// Returns the root-relative URL of a static asset, with a content hash for
+/// cache busting.
+///
+/// Only the path of `base_url` is used, so assets load from the host serving
+/// the page, such as `tars serve`. The URL is percent-encoded, so it's safe to
+/// use in HTML attributes.
+fn asset_url(
+    static_dirs: &[PathBuf],
+    static_prefix: &str,
+    base_url: Option<&str>,
+    path: &str,
+) -> Result<Value, Error> {
+    let path = path.trim_start_matches('/');
+    let outside = || {
+        invalid(format!(
+            "static asset '{path}' is outside the static directories"
+        ))
+    };
+
+    let rel_path = Path::new(path);
+    if !rel_path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside());
+    }
+
+    let (dir, file) = static_dirs
+        .iter()
+        .map(|dir| (dir, dir.join(rel_path)))
+        .find(|(_, file)| file.is_file())
+        .ok_or_else(|| invalid(format!("static asset '{path}' not found")))?;
+
+    // Symbolic links may also point outside the static directory.
+    match (file.canonicalize(), dir.canonicalize()) {
+        (Ok(file), Ok(dir)) if file.starts_with(&dir) => {}
+        _ => return Err(outside()),
+    }
+
+    let digest = sha256::try_digest(file.as_path())
+        .map_err(|e| invalid(format!("failed to hash static asset '{path}': {e}")))?;
+
+    let base_url = base_url.unwrap_or("");
+    let base_path = match base_url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => base_url,
+    };
+    let base_path = utf8_percent_encode(base_path.trim_end_matches('/'), URL_PATH);
+    let static_prefix = utf8_percent_encode(static_prefix.trim_matches('/'), URL_PATH);
+    let path: Vec<String> = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, URL_SEGMENT).to_string())
+        .collect();
+
+    Ok(Value::from_safe_string(format!(
+        "{base_path}/{static_prefix}/{}?h={}",
+        path.join("/"),
+        &digest[..8]
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn render(tmpl: &str, ctx: Value) -> Result<String, Error> {
+        let mut env = Environment::new();
+        let config = Config::default();
+        register(&mut env, &config, &config.roots());
+        env.render_str(tmpl, ctx)
+    }
+
+    fn pages() -> Value {
+        Value::from_serialize(json!([
+            { "path": "index.html", "meta": { "title": "Home" } },
+            { "path": "blog/index.html", "meta": { "title": "Blog" } },
+            { "path": "blog/first.html", "meta": { "title": "First" } },
+            { "path": "blog/2024/second.html", "meta": { "title": "Second" } },
+        ]))
+    }
+
+    #[test]
+    fn test_markdown() {
+        let out = render("{{ '*hi*' | markdown }}", context! {}).unwrap();
+        assert_eq!(out.trim(), "<p><em>hi</em></p>");
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Rust -- 2024  "), "rust-2024");
+        assert_eq!(slugify("Über Café"), "über-café");
+    }
+
+    #[test]
+    fn test_truncate_words() {
+        let ctx = context! { s => "one two three four" };
+        assert_eq!(
+            render("{{ s | truncate_words(2) }}", ctx.clone()).unwrap(),
+            "one two…"
+        );
+        assert_eq!(
+            render("{{ s | truncate_words(count=3, end='...') }}", ctx.clone()).unwrap(),
+            "one two three..."
+        );
+        assert_eq!(
+            render("{{ s | truncate_words(10) }}", ctx).unwrap(),
+            "one two three four"
+        );
+    }
+
+    #[test]
+    fn test_json_encode() {
+        let ctx = context! { v => json!({ "a": [1, 2] }) };
+        assert_eq!(
+            render("{{ v | json_encode }}", ctx.clone()).unwrap(),
+            r#"{"a":[1,2]}"#
+        );
+        assert!(
+            render("{{ v | json_encode(pretty=true) }}", ctx)
+                .unwrap()
+                .contains('\n')
+        );
+    }
+
+    #[test]
+    fn test_json_encode_escapes_html() {
+        let ctx = context! { v => json!({ "s": "</script><script>alert('x') && 1</script>" }) };
+        let out = render("{{ v | json_encode }}", ctx).unwrap();
+
+        assert!(!out.contains(['<', '>', '&', '\'']));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&out).unwrap()["s"],
+            "</script><script>alert('x') && 1</script>"
+        );
+    }
+
+    #[test]
+    fn test_date() {
+        let out = render("{{ '2024-03-05' | date('%d/%m/%Y') }}", context! {}).unwrap();
+        assert_eq!(out, "05/03/2024");
+
+        let out = render("{{ '2024-03-05T10:30:00Z' | date('%H:%M') }}", context! {}).unwrap();
+        assert_eq!(out, "10:30");
+
+        let out = render("{{ 0 | date }}", context! {}).unwrap();
+        assert_eq!(out, "1970-01-01");
+
+        assert!(render("{{ 'yesterday' | date }}", context! {}).is_err());
+    }
+
+    #[test]
+    fn test_reading_time() {
+        let words = vec!["word"; 450].join(" ");
+        assert_eq!(reading_time(&words, None), 3);
+        assert_eq!(reading_time(&words, Some(450)), 1);
+        assert_eq!(
+            reading_time("<p class=\"long attribute list\">hi</p>", None),
+            1
+        );
+    }
+
+    #[test]
+    fn test_get_page() {
+        let ctx = context! { pages => pages() };
+        let out = render("{{ get_page('blog/first.md').meta.title }}", ctx.clone()).unwrap();
+        assert_eq!(out, "First");
+
+        assert!(render("{{ get_page('missing.md') }}", ctx).is_err());
+    }
+
+    #[test]
+    fn test_get_section() {
+        let ctx = context! { pages => pages() };
+        let out = render(
+            "{% set s = get_section('blog') %}{{ s.index.meta.title }}:\
+             {% for p in s.pages %}{{ p.meta.title }},{% endfor %}",
+            ctx.clone(),
+        )
+        .unwrap();
+        assert_eq!(out, "Blog:First,Second,");
+
+        assert!(render("{{ get_section('docs') }}", ctx).is_err());
+    }
+
+    #[test]
+    fn test_load_data() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("nav.toml"), "links = ['a', 'b']").unwrap();
+
+        let value = load_data(dir.path(), "nav.toml").unwrap();
+        assert_eq!(value.get_attr("links").unwrap().len(), Some(2));
+
+        assert!(load_data(dir.path(), "missing.json").is_err());
+        assert!(load_data(dir.path(), "nav.txt").is_err());
+    }
+
+    #[test]
+    fn test_load_data_outside_data_dir() {
+        let dir = tempdir().unwrap();
+        let data_root = dir.path().join("data");
+        std::fs::create_dir(&data_root).unwrap();
+        std::fs::write(dir.path().join("secret.toml"), "key = 'value'").unwrap();
+
+        assert!(load_data(&data_root, "../secret.toml").is_err());
+
+        let absolute = dir.path().join("secret.toml");
+        assert!(load_data(&data_root, &absolute.to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn test_now() {
+        assert!(DateTime::parse_from_rfc3339(&now()).is_ok());
+
+        let year = render("{{ now() | date('%Y') }}", context! {}).unwrap();
+        assert_eq!(year, Local::now().format("%Y").to_string());
+    }
+
+    #[test]
+    fn test_asset_url() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("css")).unwrap();
+        std::fs::write(dir.path().join("css/site.css"), "body {}").unwrap();
+
+        let dirs = [dir.path().to_path_buf()];
+
+        let url = asset_url(&dirs, "static", None, "css/site.css").unwrap();
+        assert!(url.as_str().unwrap().starts_with("/static/css/site.css?h="));
+        assert!(url.is_safe());
+
+        let url = asset_url(
+            &dirs,
+            "/static/",
+            Some("https://example.com/"),
+            "/css/site.css",
+        )
+        .unwrap();
+        assert!(url.as_str().unwrap().starts_with("/static/css/site.css?h="));
+
+        let url = asset_url(
+            &dirs,
+            "static",
+            Some("https://example.com/blog/"),
+            "css/site.css",
+        )
+        .unwrap();
+        assert!(
+            url.as_str()
+                .unwrap()
+                .starts_with("/blog/static/css/site.css?h=")
+        );
+
+        assert!(asset_url(&dirs, "static", None, "missing.css").is_err());
+    }
+
+    #[test]
+    fn test_asset_url_encodes_path() {
+        let dir = tempdir().unwrap();
+        let name = "a \"b\" <c>&'d'%.css";
+        std::fs::write(dir.path().join(name), "body {}").unwrap();
+
+        let dirs = [dir.path().to_path_buf()];
+        let url = asset_url(&dirs, "static", None, name).unwrap();
+        assert!(
+            url.as_str()
+                .unwrap()
+                .starts_with("/static/a%20%22b%22%20%3Cc%3E%26%27d%27%25.css?h=")
+        );
+    }
+
+    #[test]
+    fn test_asset_url_outside_static_dirs() {
+        let root = tempdir().unwrap();
+        let dir = root.path().join("static");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(root.path().join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.path().join("secret.txt"), dir.join("link.txt")).unwrap();
+
+        let dirs = [dir];
+        let mut paths = vec!["../secret.txt", "css/../../secret.txt"];
+        if cfg!(unix) {
+            paths.push("link.txt");
+        }
+
+        for path in paths {
+            let err = asset_url(&dirs, "static", None, path).unwrap_err();
+            assert!(
+                err.to_string().contains("outside the static directories"),
+                "{err}"
+            );
+        }
+    }
+}
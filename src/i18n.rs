@@ -0,0 +1,535 @@
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    config::{Config, Language},
+    data::load_data_file,
+};
+
+/// Determine the language of a content file from its path relative to the
+/// content directory.
+///
+/// A language is selected by a filename suffix (`post.de.md`) or by a
+/// top-level directory named after the language code (`de/post.md`). Files
+/// matching neither belong to the default language. Returns the language,
+/// along with the path with any language markers removed, which is shared
+/// between translations of the same page.
+///
+/// If no languages are configured, `None` is returned along with the
+/// unmodified path.
+pub fn split_language<'a>(config: &'a Config, rel_path: &Path) -> (Option<&'a Language>, PathBuf) {
+    if config.languages.is_empty() {
+        return (None, rel_path.to_path_buf());
+    }
+
+    let mut key = rel_path.to_path_buf();
+    let mut lang = None;
+
+    // Filename suffix, e.g. `post.de.md`
+    if let Some(stem) = rel_path.file_stem().map(Path::new)
+        && let Some(code) = stem.extension().and_then(|e| e.to_str())
+        && let Some(l) = config.language(code)
+    {
+        let mut file_name = stem.with_extension("").into_os_string();
+        if let Some(ext) = rel_path.extension() {
+            file_name.push(".");
+            file_name.push(ext);
+        }
+
+        key.set_file_name(file_name);
+        lang = Some(l);
+    }
+
+    // Per-language directory, e.g. `de/post.md`
+    let mut components = key.components();
+    if let Some(Component::Normal(first)) = components.next()
+        && let Some(l) = first.to_str().and_then(|c| config.language(c))
+        && lang.is_none_or(|lang| lang.code == l.code)
+        && components.clone().next().is_some()
+    {
+        key = components.as_path().to_path_buf();
+        lang = Some(l);
+    }
+
+    (lang.or(config.default_language()), key)
+}
+
+/// A translated message, either a single string or a set of plural forms.
+#[derive(Debug)]
+enum Message {
+    Single(String),
+    Plural(HashMap<String, String>),
+}
+
+const PLURAL_FORMS: &[&str] = &["zero", "one", "two", "few", "many", "other"];
+
+/// CLDR cardinal plural rules for whole numbers, grouped by the languages
+/// sharing them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PluralRule {
+    /// No plural forms, e.g. Japanese and Chinese.
+    Other,
+    /// `one` for 1, e.g. English and German.
+    One,
+    /// `one` for 0 and 1, e.g. Hindi and Persian.
+    ZeroOne,
+    /// `one` for 1, and `many` for millions, e.g. Spanish and Italian.
+    OneMany,
+    /// `one` for 0 and 1, and `many` for millions, e.g. French.
+    ZeroOneMany,
+    /// Russian, Ukrainian and Belarusian.
+    EastSlavic,
+    /// Polish.
+    Polish,
+    /// Czech and Slovak.
+    Czech,
+    /// Croatian, Serbian and Bosnian.
+    Serbian,
+    Lithuanian,
+    Latvian,
+    Romanian,
+    Slovenian,
+    Arabic,
+    Hebrew,
+    Irish,
+    Welsh,
+    Maltese,
+}
+
+impl PluralRule {
+    /// Returns the rule for a language code, by its primary subtag.
+    ///
+    /// Languages without a known rule use `one` for 1.
+    fn for_language(code: &str) -> Self {
+        let primary = code.split(['-', '_']).next().unwrap_or(code);
+
+        match primary.to_ascii_lowercase().as_str() {
+            "ja" | "zh" | "ko" | "vi" | "th" | "id" | "ms" | "lo" | "my" | "km" | "yue" => {
+                Self::Other
+            }
+            "hi" | "bn" | "fa" | "gu" | "kn" | "mr" | "zu" | "am" | "hy" => Self::ZeroOne,
+            "es" | "it" | "ca" => Self::OneMany,
+            "fr" | "pt" => Self::ZeroOneMany,
+            "ru" | "uk" | "be" => Self::EastSlavic,
+            "pl" => Self::Polish,
+            "cs" | "sk" => Self::Czech,
+            "hr" | "sr" | "bs" | "sh" => Self::Serbian,
+            "lt" => Self::Lithuanian,
+            "lv" => Self::Latvian,
+            "ro" | "mo" => Self::Romanian,
+            "sl" => Self::Slovenian,
+            "ar" => Self::Arabic,
+            "he" | "iw" => Self::Hebrew,
+            "ga" => Self::Irish,
+            "cy" => Self::Welsh,
+            "mt" => Self::Maltese,
+            _ => Self::One,
+        }
+    }
+
+    /// Returns the plural forms the rule selects between.
+    fn forms(self) -> &'static [&'static str] {
+        match self {
+            Self::Other => &["other"],
+            Self::One | Self::ZeroOne => &["one", "other"],
+            Self::Czech => &["one", "few", "other"],
+            Self::OneMany | Self::ZeroOneMany => &["one", "many", "other"],
+            Self::EastSlavic | Self::Polish => &["one", "few", "many", "other"],
+            Self::Serbian | Self::Romanian => &["one", "few", "other"],
+            Self::Lithuanian => &["one", "few", "other"],
+            Self::Latvian => &["zero", "one", "other"],
+            Self::Slovenian => &["one", "two", "few", "other"],
+            Self::Arabic | Self::Welsh => &["zero", "one", "two", "few", "many", "other"],
+            Self::Hebrew => &["one", "two", "other"],
+            Self::Irish | Self::Maltese => &["one", "two", "few", "many", "other"],
+        }
+    }
+
+    /// Returns the plural form for `count`.
+    fn select(self, count: i64) -> &'static str {
+        let n = count.unsigned_abs();
+        let (n10, n100) = (n % 10, n % 100);
+
+        match self {
+            Self::Other => "other",
+            Self::One | Self::Czech if n == 1 => "one",
+            Self::One => "other",
+            Self::Czech if (2..=4).contains(&n) => "few",
+            Self::Czech => "other",
+            Self::ZeroOne if n <= 1 => "one",
+            Self::ZeroOne => "other",
+            Self::OneMany if n == 1 => "one",
+            Self::ZeroOneMany if n <= 1 => "one",
+            Self::OneMany | Self::ZeroOneMany if n != 0 && n.is_multiple_of(1_000_000) => "many",
+            Self::OneMany | Self::ZeroOneMany => "other",
+            Self::EastSlavic | Self::Serbian if n10 == 1 && n100 != 11 => "one",
+            Self::Polish if n == 1 => "one",
+            Self::EastSlavic | Self::Polish | Self::Serbian
+                if (2..=4).contains(&n10) && !(12..=14).contains(&n100) =>
+            {
+                "few"
+            }
+            Self::EastSlavic | Self::Polish => "many",
+            Self::Serbian => "other",
+            Self::Lithuanian if n10 == 1 && !(11..=19).contains(&n100) => "one",
+            Self::Lithuanian if n10 >= 2 && !(11..=19).contains(&n100) => "few",
+            Self::Lithuanian => "other",
+            Self::Latvian if n10 == 0 || (11..=19).contains(&n100) => "zero",
+            Self::Latvian if n10 == 1 => "one",
+            Self::Latvian => "other",
+            Self::Romanian if n == 1 => "one",
+            Self::Romanian if n == 0 || (2..=19).contains(&n100) => "few",
+            Self::Romanian => "other",
+            Self::Slovenian => match n100 {
+                1 => "one",
+                2 => "two",
+                3 | 4 => "few",
+                _ => "other",
+            },
+            Self::Arabic => match n {
+                0 => "zero",
+                1 => "one",
+                2 => "two",
+                _ if (3..=10).contains(&n100) => "few",
+                _ if n100 >= 11 => "many",
+                _ => "other",
+            },
+            Self::Hebrew => match n {
+                1 => "one",
+                2 => "two",
+                _ => "other",
+            },
+            Self::Irish => match n {
+                1 => "one",
+                2 => "two",
+                3..=6 => "few",
+                7..=10 => "many",
+                _ => "other",
+            },
+            Self::Welsh => match n {
+                0 => "zero",
+                1 => "one",
+                2 => "two",
+                3 => "few",
+                6 => "many",
+                _ => "other",
+            },
+            Self::Maltese => match n {
+                1 => "one",
+                2 => "two",
+                _ if n == 0 || (3..=10).contains(&n100) => "few",
+                _ if (11..=19).contains(&n100) => "many",
+                _ => "other",
+            },
+        }
+    }
+}
+
+/// Translation string catalogs for each configured language.
+#[derive(Debug, Default)]
+pub struct Catalogs {
+    default_lang: Option<String>,
+    strict: bool,
+    langs: HashMap<String, HashMap<String, Message>>,
+}
+
+impl Catalogs {
+    /// Load string catalogs for all configured languages.
+    ///
+    /// Each language reads `<i18n_root>/<code>.toml`, `.yaml` or `.yml`, if
+    /// present. Nested tables are flattened into dotted keys, and tables
+    /// containing only plural forms (`zero`, `one`, `other`, ...) are treated
+    /// as pluralized messages.
+    pub fn load(config: &Config, i18n_root: &Path) -> Result<Self> {
+        let mut langs = HashMap::new();
+
+        for lang in &config.languages {
+            let mut messages = HashMap::new();
+
+            for ext in ["toml", "yaml", "yml"] {
+                let path = i18n_root.join(format!("{}.{}", lang.code, ext));
+                if !path.is_file() {
+                    continue;
+                }
+
+                if let Some(value) = load_data_file(&path)? {
+                    flatten_messages(&path, "", &value, &mut messages)?;
+                    println!("Loaded translations {}", path.display());
+                }
+            }
+
+            check_plural_forms(&lang.code, &messages)?;
+            langs.insert(lang.code.clone(), messages);
+        }
+
+        Ok(Self {
+            default_lang: config.default_language().map(|l| l.code.clone()),
+            strict: config.build.i18n_strict,
+            langs,
+        })
+    }
+
+    /// Translate `key` into `lang`, falling back to the default language.
+    ///
+    /// `{name}` placeholders are replaced by the matching argument. A `count`
+    /// argument selects between plural forms by the language's CLDR plural
+    /// rules, or `zero` if given for a count of 0, falling back to `other`.
+    /// Missing keys are an error in strict mode, otherwise the key itself is
+    /// returned.
+    pub fn translate(
+        &self,
+        lang: Option<&str>,
+        key: &str,
+        args: &HashMap<String, String>,
+        count: Option<i64>,
+    ) -> Result<String> {
+        let lookup = |code: Option<&str>| {
+            let (code, messages) = self.langs.get_key_value(code?)?;
+            Some((code.as_str(), messages.get(key)?))
+        };
+
+        let (code, message) = match lookup(lang).or_else(|| lookup(self.default_lang.as_deref())) {
+            Some(found) => found,
+            None if self.strict => {
+                return Err(anyhow!(
+                    "missing translation for '{}' in language '{}'",
+                    key,
+                    lang.or(self.default_lang.as_deref()).unwrap_or("none")
+                ));
+            }
+            None => return Ok(key.to_string()),
+        };
+
+        let text = match message {
+            Message::Single(text) => text,
+            Message::Plural(forms) => {
+                let form = match count {
+                    Some(0) if forms.contains_key("zero") => "zero",
+                    Some(count) => PluralRule::for_language(code).select(count),
+                    None => "other",
+                };
+
+                forms.get(form).unwrap_or(&forms["other"])
+            }
+        };
+
+        let mut text = text.clone();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{name}}}"), value);
+        }
+
+        Ok(text)
+    }
+}
+
+/// Check that plural messages only use forms the language's plural rules
+/// select, besides `zero`, which may be given for a count of 0.
+fn check_plural_forms(code: &str, messages: &HashMap<String, Message>) -> Result<()> {
+    let rule = PluralRule::for_language(code);
+
+    for (key, message) in messages {
+        let Message::Plural(forms) = message else {
+            continue;
+        };
+
+        let mut unused: Vec<_> = forms
+            .keys()
+            .filter(|form| *form != "zero" && !rule.forms().contains(&form.as_str()))
+            .collect();
+        unused.sort();
+
+        if !unused.is_empty() {
+            return Err(anyhow!(
+                "translation '{}' in language '{}' has plural forms {:?}, but the language only uses {:?}",
+                key,
+                code,
+                unused,
+                rule.forms()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn flatten_messages(
+    path: &Path,
+    prefix: &str,
+    value: &Value,
+    messages: &mut HashMap<String, Message>,
+) -> Result<()> {
+    match value {
+        Value::Object(map) => {
+            let is_plural = map.contains_key("other")
+                && map
+                    .iter()
+                    .all(|(k, v)| PLURAL_FORMS.contains(&k.as_str()) && v.is_string());
+
+            if is_plural && !prefix.is_empty() {
+                let forms = map
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+                    .collect();
+                messages.insert(prefix.to_string(), Message::Plural(forms));
+                return Ok(());
+            }
+
+            for (k, v) in map {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{prefix}.{k}")
+                };
+
+                flatten_messages(path, &key, v, messages)?;
+            }
+        }
+        Value::String(s) => {
+            messages.insert(prefix.to_string(), Message::Single(s.clone()));
+        }
+        _ => {
+            return Err(anyhow!(
+                "translation '{}' in {} must be a string or table",
+                prefix,
+                path.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn config(codes: &[&str]) -> Config {
+        Config {
+            languages: codes
+                .iter()
+                .map(|code| Language {
+                    code: code.to_string(),
+                    title: None,
+                    base_path: None,
+                    default: false,
+                    site: Default::default(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn translate(catalogs: &Catalogs, lang: &str, count: i64) -> String {
+        catalogs
+            .translate(Some(lang), "files", &HashMap::new(), Some(count))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_plural_forms() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("pl.toml"),
+            "[files]\none = \"plik\"\nfew = \"pliki\"\nmany = \"plików\"\nother = \"pliku\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("ru.toml"),
+            "[files]\none = \"файл\"\nfew = \"файла\"\nmany = \"файлов\"\nother = \"файла\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("ar.toml"),
+            "[files]\nzero = \"0\"\none = \"1\"\ntwo = \"2\"\nfew = \"few\"\nmany = \"many\"\nother = \"other\"\n",
+        )
+        .unwrap();
+
+        let catalogs = Catalogs::load(&config(&["pl", "ru", "ar"]), dir.path()).unwrap();
+
+        assert_eq!(translate(&catalogs, "pl", 1), "plik");
+        assert_eq!(translate(&catalogs, "pl", 3), "pliki");
+        assert_eq!(translate(&catalogs, "pl", 22), "pliki");
+        assert_eq!(translate(&catalogs, "pl", 5), "plików");
+        assert_eq!(translate(&catalogs, "pl", 12), "plików");
+        assert_eq!(translate(&catalogs, "pl", 21), "plików");
+
+        assert_eq!(translate(&catalogs, "ru", 21), "файл");
+        assert_eq!(translate(&catalogs, "ru", 11), "файлов");
+        assert_eq!(translate(&catalogs, "ru", 24), "файла");
+
+        assert_eq!(translate(&catalogs, "ar", 0), "0");
+        assert_eq!(translate(&catalogs, "ar", 2), "2");
+        assert_eq!(translate(&catalogs, "ar", 105), "few");
+        assert_eq!(translate(&catalogs, "ar", 11), "many");
+        assert_eq!(translate(&catalogs, "ar", 100), "other");
+    }
+
+    #[test]
+    fn test_czech_plural_forms() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("cs.toml"),
+            "[files]\none = \"soubor\"\nfew = \"soubory\"\nother = \"souborů\"\n",
+        )
+        .unwrap();
+
+        let catalogs = Catalogs::load(&config(&["cs"]), dir.path()).unwrap();
+
+        assert_eq!(translate(&catalogs, "cs", 1), "soubor");
+        assert_eq!(translate(&catalogs, "cs", 3), "soubory");
+        assert_eq!(translate(&catalogs, "cs", 5), "souborů");
+    }
+
+    #[test]
+    fn test_plural_rules_select_own_forms() {
+        let rules = [
+            PluralRule::Other,
+            PluralRule::One,
+            PluralRule::ZeroOne,
+            PluralRule::OneMany,
+            PluralRule::ZeroOneMany,
+            PluralRule::EastSlavic,
+            PluralRule::Polish,
+            PluralRule::Czech,
+            PluralRule::Serbian,
+            PluralRule::Lithuanian,
+            PluralRule::Latvian,
+            PluralRule::Romanian,
+            PluralRule::Slovenian,
+            PluralRule::Arabic,
+            PluralRule::Hebrew,
+            PluralRule::Irish,
+            PluralRule::Welsh,
+            PluralRule::Maltese,
+        ];
+
+        for rule in rules {
+            for count in (0..=1000).chain([1_000_000]) {
+                let form = rule.select(count);
+                assert!(
+                    rule.forms().contains(&form),
+                    "{rule:?} selects {form} for {count}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_unused_plural_forms() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("en.toml"),
+            "[files]\none = \"file\"\nfew = \"files\"\nother = \"files\"\n",
+        )
+        .unwrap();
+
+        let err = Catalogs::load(&config(&["en"]), dir.path()).unwrap_err();
+        assert!(err.to_string().contains("\"few\""));
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{Result, anyhow};
+use include_dir::{Dir, DirEntry, include_dir};
+use std::{
+    fs,
+    io::{IsTerminal, Write},
+    path::Path,
+};
+use walkdir::WalkDir;
+
+use crate::args::InitArgs;
+
+/// Starter projects embedded in the binary, one per subdirectory.
+static STARTERS: Dir = include_dir!("$CARGO_MANIFEST_DIR/starters");
+
+pub const DEFAULT_STARTER: &str = "default";
+
+const DEFAULT_TITLE: &str = "My Site";
+
+/// Values substituted into starter files.
+///
+/// Each placeholder in a text file is replaced by the matching value. In
+/// TOML files, values are escaped as basic strings, and keys set to an empty
+/// value are left out.
+struct StarterVars {
+    title: String,
+    author: String,
+    base_url: String,
+}
+
+impl StarterVars {
+    fn from_args(args: &InitArgs) -> Result<Self> {
+        Ok(Self {
+            title: resolve_var(&args.title, "Site title", DEFAULT_TITLE)?,
+            author: resolve_var(&args.author, "Author", "")?,
+            base_url: resolve_var(&args.base_url, "Base URL", "")?,
+        })
+    }
+
+    fn placeholders(&self) -> [(&'static str, &str); 3] {
+        [
+            ("__SITE_TITLE__", &self.title),
+            ("__SITE_AUTHOR__", &self.author),
+            ("__BASE_URL__", &self.base_url),
+        ]
+    }
+
+    fn substitute(&self, path: &Path, text: &str) -> String {
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            return self.substitute_toml(text);
+        }
+
+        let mut text = text.to_string();
+        for (placeholder, value) in self.placeholders() {
+            text = text.replace(placeholder, value);
+        }
+
+        text
+    }
+
+    fn substitute_toml(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+
+        for line in text.split_inclusive('\n') {
+            let value = line.trim().split_once('=').map(|(_, v)| v.trim());
+            let is_empty = self.placeholders().iter().any(|(placeholder, v)| {
+                v.is_empty() && value == Some(&format!("\"{placeholder}\""))
+            });
+
+            if is_empty {
+                continue;
+            }
+
+            let mut line = line.to_string();
+            for (placeholder, value) in self.placeholders() {
+                line = line.replace(placeholder, &escape_toml(value));
+            }
+            out.push_str(&line);
+        }
+
+        out
+    }
+}
+
+/// Escape a value for use within a TOML basic string.
+fn escape_toml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Use the value passed on the command line, or prompt for one if running
+/// interactively.
+fn resolve_var(value: &Option<String>, prompt: &str, default: &str) -> Result<String> {
+    if let Some(value) = value {
+        return Ok(value.clone());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        return Ok(default.to_string());
+    }
+
+    print!("{prompt} [{default}]: ");
+    std::io::stdout().flush()?;
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+
+    let input = input.trim();
+    if input.is_empty() {
+        Ok(default.to_string())
+    } else {
+        Ok(input.to_string())
+    }
+}
+
+/// Returns the names of all embedded starters.
+pub fn starter_names() -> Vec<&'static str> {
+    STARTERS.dirs().filter_map(|d| d.path().to_str()).collect()
+}
+
+fn is_dir_empty(path: &Path) -> std::io::Result<bool> {
+    let mut entries = std::fs::read_dir(path)?;
+    Ok(entries.next().is_none())
+}
+
+/// Write a starter file, substituting variables if it contains text.
+fn write_file(dst: &Path, contents: &[u8], vars: &StarterVars) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match std::str::from_utf8(contents) {
+        Ok(text) => fs::write(dst, vars.substitute(dst, text))?,
+        Err(_) => fs::write(dst, contents)?,
+    }
+
+    Ok(())
+}
+
+fn extract_embedded(
+    dir: &Dir,
+    starter_root: &Path,
+    root_dir: &Path,
+    vars: &StarterVars,
+) -> Result<()> {
+    for entry in dir.entries() {
+        match entry {
+            DirEntry::Dir(d) => extract_embedded(d, starter_root, root_dir, vars)?,
+            DirEntry::File(f) => {
+                let rel_path = f.path().strip_prefix(starter_root)?;
+                write_file(&root_dir.join(rel_path), f.contents(), vars)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_local(starter_dir: &Path, root_dir: &Path, vars: &StarterVars) -> Result<()> {
+    let walker = WalkDir::new(starter_dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git");
+
+    for entry in walker {
+        let entry = entry?;
+        let rel_path = entry.path().strip_prefix(starter_dir)?;
+        let dst = root_dir.join(rel_path);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dst)?;
+        } else if entry.file_type().is_file() {
+            write_file(&dst, &fs::read(entry.path())?, vars)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a new project from a starter.
+///
+/// `args.starter` names either an embedded starter, or a local directory to
+/// use as a template project.
+pub fn init_project(args: &InitArgs) -> Result<()> {
+    let root_dir = Path::new(&args.path);
+
+    if !root_dir.is_dir() {
+        return Err(anyhow!(
+            "Directory '{}' does not exist.",
+            root_dir.display()
+        ));
+    }
+
+    let dir_empty = is_dir_empty(root_dir).unwrap_or(false);
+
+    if !args.force && !dir_empty {
+        return Err(anyhow!(
+            "Directory '{}' not empty, or not readable.",
+            root_dir.display()
+        ));
+    }
+
+    let embedded = STARTERS
+        .dirs()
+        .find(|d| d.path() == Path::new(&args.starter));
+
+    if let Some(starter) = embedded {
+        let vars = StarterVars::from_args(args)?;
+        extract_embedded(starter, starter.path(), root_dir, &vars)?;
+    } else if Path::new(&args.starter).is_dir() {
+        let starter_dir = Path::new(&args.starter);
+        if root_dir
+            .canonicalize()?
+            .starts_with(starter_dir.canonicalize()?)
+        {
+            return Err(anyhow!(
+                "cannot create a project inside its starter directory"
+            ));
+        }
+
+        let vars = StarterVars::from_args(args)?;
+        copy_local(starter_dir, root_dir, &vars)?;
+    } else {
+        return Err(anyhow!(
+            "unknown starter '{}', expected a directory or one of: {}",
+            args.starter,
+            starter_names().join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(title: &str, author: &str, base_url: &str) -> StarterVars {
+        StarterVars {
+            title: title.to_string(),
+            author: author.to_string(),
+            base_url: base_url.to_string(),
+        }
+    }
+
+    fn starter_config(name: &str, vars: &StarterVars) -> toml::Table {
+        let file = STARTERS
+            .get_file(format!("{name}/tars.toml"))
+            .and_then(|f| f.contents_utf8())
+            .unwrap();
+
+        toml::from_str(&vars.substitute(Path::new("tars.toml"), file)).unwrap()
+    }
+
+    fn init_args(path: &Path, starter: &str) -> InitArgs {
+        InitArgs {
+            force: false,
+            path: path.to_string_lossy().into_owned(),
+            starter: starter.to_string(),
+            title: Some("Test Site".to_string()),
+            author: Some(String::new()),
+            base_url: Some(String::new()),
+        }
+    }
+
+    #[test]
+    fn test_init_embedded_starter() {
+        let dir = tempfile::tempdir().unwrap();
+        init_project(&init_args(dir.path(), DEFAULT_STARTER)).unwrap();
+
+        let config: toml::Table =
+            toml::from_str(&fs::read_to_string(dir.path().join("tars.toml")).unwrap()).unwrap();
+        assert_eq!(config["site"]["title"].as_str(), Some("Test Site"));
+        assert!(dir.path().join("template/default.html").is_file());
+
+        let err = init_project(&init_args(dir.path(), DEFAULT_STARTER)).unwrap_err();
+        assert!(err.to_string().contains("not empty"), "{err}");
+    }
+
+    #[test]
+    fn test_init_local_starter() {
+        let dir = tempfile::tempdir().unwrap();
+        let starter = dir.path().join("starter");
+        let project = dir.path().join("project");
+        fs::create_dir_all(starter.join(".git")).unwrap();
+        fs::create_dir_all(starter.join("content")).unwrap();
+        fs::create_dir(&project).unwrap();
+        fs::write(starter.join(".git/HEAD"), "ref").unwrap();
+        fs::write(starter.join("content/index.md"), "# __SITE_TITLE__").unwrap();
+
+        let starter_name = starter.to_string_lossy();
+        init_project(&init_args(&project, &starter_name)).unwrap();
+        assert_eq!(
+            fs::read_to_string(project.join("content/index.md")).unwrap(),
+            "# Test Site"
+        );
+        assert!(!project.join(".git").exists());
+
+        let inner = starter.join("content/new");
+        fs::create_dir(&inner).unwrap();
+        let err = init_project(&init_args(&inner, &starter_name)).unwrap_err();
+        assert!(err.to_string().contains("inside its starter"), "{err}");
+
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        let err = init_project(&init_args(&empty, "missing")).unwrap_err();
+        assert!(
+            err.to_string().starts_with("unknown starter 'missing'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_substitute_escapes_toml() {
+        let title = r#"My "Blog" \ Notes"#;
+        let vars = vars(title, r"C:\Users", "https://example.com");
+
+        for name in starter_names() {
+            let config = starter_config(name, &vars);
+            assert_eq!(config["site"]["title"].as_str(), Some(title));
+            assert_eq!(config["site"]["author"].as_str(), Some(r"C:\Users"));
+        }
+    }
+
+    #[test]
+    fn test_substitute_omits_empty_values() {
+        let config = starter_config(DEFAULT_STARTER, &vars(DEFAULT_TITLE, "", ""));
+
+        assert_eq!(config["site"]["title"].as_str(), Some(DEFAULT_TITLE));
+        assert!(!config["site"].as_table().unwrap().contains_key("author"));
+        assert!(!config["site"].as_table().unwrap().contains_key("base_url"));
+    }
+
+    #[test]
+    fn test_substitute_text() {
+        let vars = vars(r#"My "Blog""#, "", "");
+        let text = vars.substitute(Path::new("index.md"), "Welcome to __SITE_TITLE__!");
+
+        assert_eq!(text, r#"Welcome to My "Blog"!"#);
+    }
+}
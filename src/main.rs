@@ -1,62 +1,45 @@
-use anyhow::{Result, anyhow};
-use std::{collections::HashMap, path::Path, process::exit, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 use crate::{
-    args::{DEFAULT_TARS_CONFIG_FILE, InitArgs, PluginSubcommand, TarsSubcommand, parse_args},
+    archetype::new_content,
+    args::{ConfigSubcommand, PluginSubcommand, TarsSubcommand, parse_args},
     build::Builder,
-    config::{Config, HookType, Plugin},
+    config::{Config, HookType, LoadOptions, Plugin},
+    init::init_project,
     serve::run_server,
 };
 
+pub mod archetype;
 pub mod args;
 pub mod build;
+pub mod cache;
 pub mod config;
+pub mod data;
+pub mod filters;
+pub mod i18n;
+pub mod init;
 pub mod markdown;
+pub mod menu;
+pub mod output;
 pub mod serve;
 pub mod template;
+pub mod validate;
 
-fn is_dir_empty(path: &Path) -> std::io::Result<bool> {
-    let mut entries = std::fs::read_dir(path)?;
-    Ok(entries.next().is_none())
-}
-
-fn init_project(args: &InitArgs, config: &Config) -> Result<()> {
-    let root_dir = Path::new(&args.path);
-
-    if !root_dir.is_dir() {
-        return Err(anyhow!(
-            "Directory '{}' does not exist.",
-            root_dir.display()
-        ));
-    }
-
-    let content_dir = root_dir.join(&config.build.content_dir);
-    let static_dir = root_dir.join(&config.build.static_dir);
-    let template_dir = root_dir.join(&config.build.template_dir);
-    let config_file = root_dir.join(DEFAULT_TARS_CONFIG_FILE);
-
-    let dir_empty = is_dir_empty(root_dir).unwrap_or(false);
+fn load_config(path: &str, options: &LoadOptions) -> Config {
+    let config = Config::from_file(path, options).unwrap_or_else(|e| {
+        eprintln!("Failed to load config file {path}: {e}");
+        exit(1);
+    });
 
-    if !args.force && !dir_empty {
-        return Err(anyhow!(
-            "Directory '{}' not empty, or not readable.",
-            root_dir.display()
-        ));
+    for warning in &config.warnings {
+        eprintln!("Warning: {warning}");
     }
 
-    std::fs::create_dir_all(content_dir)?;
-    std::fs::create_dir_all(static_dir)?;
-    std::fs::create_dir_all(template_dir)?;
-    std::fs::write(config_file, "")?;
-
-    Ok(())
-}
-
-fn load_config(path: &str) -> Config {
-    Config::from_file(path).unwrap_or_else(|e| {
-        eprintln!("Failed to load config file {path}: {e}");
-        exit(1);
-    })
+    config
 }
 
 #[tokio::main]
@@ -68,18 +51,39 @@ async fn main() {
 
     match args.subcommand {
         TarsSubcommand::Init(args) => {
-            let config = Config::default();
-
-            if let Err(e) = init_project(&args, &config) {
+            if let Err(e) = init_project(&args) {
                 println!("Error initializing project: {e}");
                 exit(1);
             } else {
                 println!("Initialized project in '{}' .", args.path);
             }
         }
+        TarsSubcommand::New(args) => {
+            let config = load_config(
+                &args.config,
+                &LoadOptions {
+                    root: args.root.map(PathBuf::from),
+                    ..Default::default()
+                },
+            );
+
+            if let Err(e) = new_content(&config, &args.path) {
+                println!("Error creating content: {e}");
+                exit(1);
+            }
+        }
         TarsSubcommand::Build(args) => {
-            let config = load_config(&args.config);
-            let mut builder = Builder::new(&config, args.no_verify, None);
+            let overrides = args.overrides.to_overrides().unwrap_or_else(|e| {
+                println!("{e}");
+                exit(1);
+            });
+            let options = LoadOptions {
+                root: args.root.map(PathBuf::from),
+                env: args.env,
+                overrides,
+            };
+            let config = load_config(&args.config, &options);
+            let mut builder = Builder::new(&config, args.no_verify, args.force);
 
             if let Err(e) = builder.build() {
                 println!("{e}");
@@ -87,8 +91,13 @@ async fn main() {
             }
         }
         TarsSubcommand::Clean(args) => {
-            let config = load_config(&args.config);
-            let builder = Builder::new(&config, false, None);
+            let options = LoadOptions {
+                root: args.root.map(PathBuf::from),
+                env: args.env,
+                ..Default::default()
+            };
+            let config = load_config(&args.config, &options);
+            let builder = Builder::new(&config, false, false);
 
             if let Err(e) = builder.clean() {
                 println!("{e}");
@@ -96,26 +105,71 @@ async fn main() {
             }
         }
         TarsSubcommand::Serve(args) => {
-            let config = load_config(&args.config);
-
-            if let Err(e) = run_server(Arc::new(config)).await {
+            let overrides = args.overrides.to_overrides().unwrap_or_else(|e| {
+                println!("{e}");
+                exit(1);
+            });
+            let options = LoadOptions {
+                root: args.root.map(PathBuf::from),
+                env: args.env,
+                overrides,
+            };
+            let config = load_config(&args.config, &options);
+
+            if let Err(e) = run_server(config, options).await {
                 println!("{e}");
                 exit(1);
             }
         }
+        TarsSubcommand::Config(args) => match args.subcommand {
+            ConfigSubcommand::Show(args) => {
+                let overrides = args.overrides.to_overrides().unwrap_or_else(|e| {
+                    println!("{e}");
+                    exit(1);
+                });
+                let options = LoadOptions {
+                    root: args.root.map(PathBuf::from),
+                    env: args.env,
+                    overrides,
+                };
+                let config = load_config(&args.config, &options);
+
+                match config.to_toml() {
+                    Ok(toml) => print!("{toml}"),
+                    Err(e) => {
+                        println!("Error serializing configuration: {e}");
+                        exit(1);
+                    }
+                }
+            }
+        },
         TarsSubcommand::Plugin(args) => match args.subcommand {
             PluginSubcommand::List(args) => {
-                let config = load_config(&args.config);
+                let config = load_config(
+                    &args.config,
+                    &LoadOptions {
+                        root: args.root.map(PathBuf::from),
+                        ..Default::default()
+                    },
+                );
 
                 for p in config.plugins {
                     println!("Name: {}, Hook: {:#?}", p.name, p.hook_type);
                 }
             }
             PluginSubcommand::Verify(args) => {
-                let config = load_config(&args.config);
+                let config = load_config(
+                    &args.config,
+                    &LoadOptions {
+                        root: args.root.map(PathBuf::from),
+                        ..Default::default()
+                    },
+                );
+
+                let plugin_dir = config.project_path(&config.build.plugin_dir);
 
                 for p in config.plugins {
-                    if let Err(e) = p.resolve(Path::new(&config.build.plugin_dir), false) {
+                    if let Err(e) = p.resolve(&plugin_dir, false) {
                         println!("Verification failed for {}: {}", p.name, e);
                         exit(1);
                     } else {
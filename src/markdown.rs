@@ -5,7 +5,10 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::config::Config;
+use crate::{
+    config::{Config, Site},
+    i18n::split_language,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FrontMatter {
@@ -24,7 +27,7 @@ pub struct FrontMatter {
 }
 
 impl FrontMatter {
-    pub fn merge_with_default(&self, config: &Config) -> Self {
+    pub fn merge_with_default(&self, site: &Site) -> Self {
         Self {
             title: self.title.clone(),
             date: self.date,
@@ -34,7 +37,7 @@ impl FrontMatter {
             template: self
                 .template
                 .clone()
-                .or(Some(config.site.default_template.clone())),
+                .or(Some(site.default_template.clone())),
             tags: self.tags.clone(),
             slug: self.slug.clone(),
             summary: self.summary.clone(),
@@ -42,14 +45,14 @@ impl FrontMatter {
         }
     }
 
-    fn new(config: &Config) -> Self {
+    fn new(site: &Site) -> Self {
         Self {
             title: None,
             date: None,
             author: None,
             page_type: None,
             draft: false,
-            template: Some(config.site.default_template.clone()),
+            template: Some(site.default_template.clone()),
             tags: None,
             slug: None,
             summary: None,
@@ -64,48 +67,135 @@ pub struct Page {
     pub path: PathBuf,
     #[serde(rename(serialize = "path"))]
     pub rel_path: PathBuf,
+    #[serde(skip)]
+    pub translation_key: PathBuf,
+    pub lang: Option<String>,
     pub meta: FrontMatter,
     pub content: String,
+    /// Problems found loading the page, such as invalid front matter.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
 }
 
 impl Page {
     /// Parse a content file into a `Page` structure
     pub fn from_file(config: &Config, src_root: &Path, path: &PathBuf) -> Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let (frontmatter, content) = split_frontmatter(config, &content);
+        let (lang, translation_key) = split_language(config, path.strip_prefix(src_root)?);
+        let site = match lang {
+            Some(lang) => config.site_for(lang),
+            None => config.site.clone(),
+        };
 
-        let options = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
-            | Options::ENABLE_TABLES
-            | Options::ENABLE_FOOTNOTES
-            | Options::ENABLE_STRIKETHROUGH
-            | Options::ENABLE_TASKLISTS;
-        let parser = Parser::new_ext(content, options);
+        let content = fs::read_to_string(path)?;
+        let (frontmatter, content, warning) = split_frontmatter(&site, &content);
 
-        let mut html_output = String::new();
-        pulldown_cmark::html::push_html(&mut html_output, parser);
+        let html_output = render_markdown(content);
 
-        let mut rel_path = path.strip_prefix(src_root)?.to_path_buf();
+        let mut rel_path = match lang {
+            Some(lang) => Path::new(&lang.base_path()).join(&translation_key),
+            None => translation_key.clone(),
+        };
         rel_path.set_extension("html");
 
         Ok(Self {
             path: path.to_path_buf(),
             rel_path,
+            translation_key,
+            lang: lang.map(|l| l.code.clone()),
             meta: frontmatter,
-            content: html_output.to_string(),
+            content: html_output,
+            warnings: warning.into_iter().collect(),
         })
     }
+
+    /// Returns the site-relative URL of the generated page.
+    pub fn url(&self) -> String {
+        let parts: Vec<String> = self
+            .rel_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        format!("/{}", parts.join("/"))
+    }
+}
+
+/// Render Markdown content to HTML
+pub fn render_markdown(content: &str) -> String {
+    let options = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(content, options);
+
+    let mut html_output = String::new();
+    pulldown_cmark::html::push_html(&mut html_output, parser);
+
+    html_output
 }
 
 /// Split frontmatter metadata from Markdown content
-fn split_frontmatter<'a>(config: &Config, content: &'a str) -> (FrontMatter, &'a str) {
-    let content = content.trim_start();
-    if content.starts_with("---") {
-        if let Some(end) = content[3..].find("---") {
-            let fm_str = &content[3..3 + end];
-            let body = &content[3 + end + 3..];
-            let fm: FrontMatter = serde_yaml::from_str(fm_str).unwrap_or(FrontMatter::new(config));
-            return (fm.merge_with_default(config), body);
+///
+/// Invalid front matter is replaced with the defaults, returning a warning
+/// which locates the problem by its line in `content`.
+fn split_frontmatter<'a>(site: &Site, content: &'a str) -> (FrontMatter, &'a str, Option<String>) {
+    let trimmed = content.trim_start();
+    if let Some(rest) = trimmed.strip_prefix("---")
+        && let Some(end) = rest.find("---")
+    {
+        let fm_str = &rest[..end];
+        let body = &rest[end + 3..];
+        if fm_str.trim().is_empty() {
+            return (FrontMatter::new(site), body, None);
         }
+
+        // Pad with the lines before the front matter, so error locations are
+        // lines of the file.
+        let skipped = content[..content.len() - rest.len()].matches('\n').count();
+        let padded = format!("{}{fm_str}", "\n".repeat(skipped));
+
+        return match serde_yaml::from_str::<FrontMatter>(&padded) {
+            Ok(fm) => (fm.merge_with_default(site), body, None),
+            Err(e) => (
+                FrontMatter::new(site),
+                body,
+                Some(format!("invalid front matter, using defaults: {e}")),
+            ),
+        };
+    }
+    (FrontMatter::new(site), trimmed, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_frontmatter() {
+        let site = Site::default();
+        let (fm, body, warning) = split_frontmatter(&site, "---\ntitle: Hi\n---\nBody\n");
+
+        assert_eq!(fm.title.as_deref(), Some("Hi"));
+        assert_eq!(body, "\nBody\n");
+        assert_eq!(warning, None);
+    }
+
+    #[test]
+    fn test_invalid_frontmatter_uses_defaults() {
+        let site = Site::default();
+        let content = "\n---\ntitle: Hi\ndate: [2024\n---\nBody\n";
+        let (fm, body, warning) = split_frontmatter(&site, content);
+
+        assert_eq!(fm.title, None);
+        assert_eq!(fm.template.as_deref(), Some(site.default_template.as_str()));
+        assert_eq!(body, "\nBody\n");
+
+        let warning = warning.unwrap();
+        assert!(
+            warning.starts_with("invalid front matter, using defaults:"),
+            "{warning}"
+        );
+        assert!(warning.contains("at line 4"), "{warning}");
     }
-    (FrontMatter::new(config), content)
 }
@@ -0,0 +1,275 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Config, MenuEntry},
+    markdown::Page,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MenuItem {
+    pub name: String,
+    pub url: String,
+    pub weight: i64,
+    pub active: bool,
+    pub child_active: bool,
+    pub children: Vec<MenuItem>,
+    #[serde(skip)]
+    page: Option<PathBuf>,
+}
+
+/// Resolved menu trees, keyed by menu name.
+#[derive(Debug, Default)]
+pub struct Menus {
+    menus: HashMap<String, Vec<MenuItem>>,
+}
+
+impl Menus {
+    /// Resolve all menus declared in `config` against the loaded pages.
+    pub fn new(config: &Config, pages: &[Page]) -> Result<Self> {
+        let mut menus = HashMap::new();
+
+        for (menu_name, entries) in &config.menus {
+            let mut roots = Vec::new();
+            let mut pending = Vec::new();
+
+            for entry in entries {
+                let item = resolve_entry(menu_name, entry, pages)?;
+
+                match &entry.parent {
+                    Some(parent) => pending.push((parent.clone(), item)),
+                    None => roots.push(item),
+                }
+            }
+
+            // Attach entries with a `parent` key, allowing parents which are
+            // themselves attached later on.
+            while !pending.is_empty() {
+                let before = pending.len();
+
+                pending.retain_mut(|(parent, item)| match find_item(&mut roots, parent) {
+                    Some(p) => {
+                        p.children.push(item.clone());
+                        false
+                    }
+                    None => true,
+                });
+
+                if pending.len() == before {
+                    let (parent, item) = &pending[0];
+                    return Err(anyhow!(
+                        "menu '{}': parent '{}' of entry '{}' not found",
+                        menu_name,
+                        parent,
+                        item.name
+                    ));
+                }
+            }
+
+            sort_items(&mut roots);
+            menus.insert(menu_name.clone(), roots);
+        }
+
+        Ok(Self { menus })
+    }
+
+    /// Return a copy of the menus with entries for `page` marked active.
+    pub fn for_page(&self, page: &Page) -> HashMap<String, Vec<MenuItem>> {
+        let url = page.url();
+        let mut menus = self.menus.clone();
+
+        for items in menus.values_mut() {
+            mark_active(items, &page.rel_path, &url);
+        }
+
+        menus
+    }
+}
+
+fn resolve_entry(menu_name: &str, entry: &MenuEntry, pages: &[Page]) -> Result<MenuItem> {
+    let (url, page) = match (&entry.url, &entry.page) {
+        (Some(url), None) => (url.clone(), None),
+        (None, Some(content_path)) => {
+            let rel_path = Path::new(content_path).with_extension("html");
+
+            let page = pages
+                .iter()
+                .find(|p| p.rel_path == rel_path)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "menu '{}': page '{}' of entry '{}' not found",
+                        menu_name,
+                        content_path,
+                        entry.name
+                    )
+                })?;
+
+            (page.url(), Some(rel_path))
+        }
+        _ => {
+            return Err(anyhow!(
+                "menu '{}': entry '{}' must have exactly one of `url` or `page`",
+                menu_name,
+                entry.name
+            ));
+        }
+    };
+
+    let children = entry
+        .children
+        .iter()
+        .map(|c| resolve_entry(menu_name, c, pages))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(MenuItem {
+        name: entry.name.clone(),
+        url,
+        weight: entry.weight,
+        active: false,
+        child_active: false,
+        children,
+        page,
+    })
+}
+
+fn find_item<'a>(items: &'a mut [MenuItem], name: &str) -> Option<&'a mut MenuItem> {
+    for item in items {
+        if item.name == name {
+            return Some(item);
+        }
+
+        if let Some(found) = find_item(&mut item.children, name) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+fn sort_items(items: &mut [MenuItem]) {
+    items.sort_by(|a, b| a.weight.cmp(&b.weight).then_with(|| a.name.cmp(&b.name)));
+
+    for item in items {
+        sort_items(&mut item.children);
+    }
+}
+
+/// Mark items matching the current page, returning whether any item was marked.
+fn mark_active(items: &mut [MenuItem], rel_path: &Path, url: &str) -> bool {
+    let mut any_active = false;
+
+    for item in items {
+        item.active = match &item.page {
+            Some(page) => page == rel_path,
+            None => item.url == url,
+        };
+        item.child_active = mark_active(&mut item.children, rel_path, url);
+
+        any_active |= item.active || item.child_active;
+    }
+
+    any_active
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn page(rel_path: &str) -> Page {
+        serde_json::from_value(
+            json!({ "rel_path": rel_path, "lang": null, "meta": {}, "content": "" }),
+        )
+        .unwrap()
+    }
+
+    fn menus(config: &str, pages: &[Page]) -> Result<Menus> {
+        let config: Config = toml::from_str(config).unwrap();
+        Menus::new(&config, pages)
+    }
+
+    fn names(items: &[MenuItem]) -> Vec<&str> {
+        items.iter().map(|item| item.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_menus() {
+        let pages = [
+            page("index.html"),
+            page("docs/index.html"),
+            page("docs/setup.html"),
+        ];
+        let menus = menus(
+            r#"
+[[menu.main]]
+name = "Source"
+url = "https://example.com"
+weight = 3
+
+[[menu.main]]
+name = "Setup"
+page = "docs/setup.md"
+parent = "Docs"
+
+[[menu.main]]
+name = "Docs"
+page = "docs/index.md"
+weight = 2
+
+[[menu.main]]
+name = "Home"
+page = "index.md"
+weight = 1
+"#,
+            &pages,
+        )
+        .unwrap();
+
+        let main = &menus.for_page(&pages[2])["main"];
+        assert_eq!(names(main), ["Home", "Docs", "Source"]);
+        assert_eq!(main[1].url, "/docs/index.html");
+        assert_eq!(names(&main[1].children), ["Setup"]);
+        assert!(!main[1].active && main[1].child_active);
+        assert!(main[1].children[0].active);
+        assert!(!main[0].active && !main[2].active);
+    }
+
+    #[test]
+    fn test_menu_errors() {
+        let pages = [page("index.html")];
+
+        let err = menus(
+            "[[menu.main]]\nname = \"A\"\npage = \"missing.md\"\n",
+            &pages,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "menu 'main': page 'missing.md' of entry 'A' not found"
+        );
+
+        let err = menus(
+            "[[menu.main]]\nname = \"A\"\nurl = \"/\"\nparent = \"B\"\n",
+            &pages,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "menu 'main': parent 'B' of entry 'A' not found"
+        );
+
+        let err = menus(
+            "[[menu.main]]\nname = \"A\"\nurl = \"/\"\npage = \"index.md\"\n",
+            &pages,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "menu 'main': entry 'A' must have exactly one of `url` or `page`"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use warp::hyper::body::Bytes;
+
+/// A generated file held in memory.
+#[derive(Clone)]
+pub enum OutputFile {
+    /// Generated contents.
+    Data(Bytes),
+    /// A file on disk, such as a static file, served without loading it.
+    Path(PathBuf),
+}
+
+/// Generated files, by path relative to the site root, which are served by
+/// `tars serve` rather than written to the build directory.
+#[derive(Default)]
+pub struct MemoryOutput {
+    files: RwLock<HashMap<PathBuf, OutputFile>>,
+}
+
+impl MemoryOutput {
+    pub fn get(&self, rel_path: &Path) -> Option<OutputFile> {
+        self.files.read().ok()?.get(rel_path).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.read().is_ok_and(|files| files.is_empty())
+    }
+
+    pub fn insert(&self, rel_path: PathBuf, file: OutputFile) {
+        if let Ok(mut files) = self.files.write() {
+            files.insert(rel_path, file);
+        }
+    }
+
+    /// Remove the file at `rel_path`, or every file under it.
+    pub fn remove(&self, rel_path: &Path) {
+        if let Ok(mut files) = self.files.write() {
+            files.retain(|path, _| !path.starts_with(rel_path));
+        }
+    }
+
+    /// Replace every file with those in `other`, leaving it empty.
+    pub fn replace(&self, other: &MemoryOutput) {
+        if let (Ok(mut files), Ok(mut other)) = (self.files.write(), other.files.write()) {
+            *files = std::mem::take(&mut *other);
+        }
+    }
+}
@@ -1,81 +1,629 @@
 use anyhow::Result;
-use notify::{Event, EventKind, RecursiveMode, Watcher};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind};
+use serde::Serialize;
 use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf, absolute};
 use std::sync::Arc;
-use std::{path::Path, sync::mpsc};
-use warp::Filter;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use warp::{
+    Filter, Rejection,
+    http::{StatusCode, header::CONTENT_TYPE},
+    path::Tail,
+};
 
-use crate::{build::Builder, config::Config};
+use crate::{
+    build::{Builder, Changes, PageErrors},
+    config::{Config, LoadOptions, ParseError},
+    output::{MemoryOutput, OutputFile},
+};
 
-const RELOAD_JS: &str = "
-<script>
-const es = new EventSource(\"/__tars_reload__\");
-es.onmessage = () => location.reload();
-</script>
+/// Page served before the site has been built.
+const WAITING_HTML: &str = "<!DOCTYPE html>
+<html>
+<head><title>Waiting for a successful build</title></head>
+<body><p>The site hasn't been built yet.</p></body>
+</html>
 ";
 
-pub async fn run_server(config: Arc<Config>) -> Result<()> {
-    let socket_str = format!("{}:{}", config.serve.host, config.serve.port);
-    let socket_addr: SocketAddr = socket_str.parse()?;
-    let build_dir = config.build.build_dir.clone();
-    let auto_reload = config.serve.auto_reload;
+/// Client injected into served pages, which reloads the page when the site is
+/// rebuilt, or only its stylesheets when just they changed, and shows build
+/// errors over the page until the next successful build.
+const RELOAD_JS: &str = r#"<script>
+(() => {
+  const events = new EventSource("/__tars_reload__");
+  const overlayId = "__tars_error__";
+  const clearErrors = () => document.getElementById(overlayId)?.remove();
 
-    let (refresh_tx, _) = tokio::sync::broadcast::channel::<()>(16);
-    let refresh_sse = refresh_tx.clone();
-    let (notify_tx, notify_rx) = mpsc::channel::<notify::Result<Event>>();
-    let mut watcher = notify::recommended_watcher(notify_tx)?;
-    watcher.watch(
-        Path::new(&config.build.content_dir),
-        RecursiveMode::Recursive,
-    )?;
-    watcher.watch(
-        Path::new(&config.build.template_dir),
-        RecursiveMode::Recursive,
-    )?;
-    watcher.watch(
-        Path::new(&config.build.static_dir),
-        RecursiveMode::Recursive,
-    )?;
+  events.addEventListener("reload", () => location.reload());
+  events.addEventListener("ok", clearErrors);
+  events.addEventListener("failed", (e) => {
+    clearErrors();
+    const overlay = document.createElement("div");
+    overlay.id = overlayId;
+    overlay.style.cssText =
+      "position:fixed;inset:0;z-index:2147483647;overflow:auto;padding:2em;" +
+      "background:rgba(24,24,24,0.95);color:#eee;font:14px/1.5 monospace;";
 
-    std::thread::spawn(move || {
-        let mut builder = Builder::new(&config, false, Some(RELOAD_JS));
-        println!("Building...");
-        if let Err(e) = builder.build() {
-            println!("Build error: {e}");
+    const title = document.createElement("h2");
+    title.textContent = "Build failed";
+    title.style.cssText = "margin:0 0 1em;color:#ff6b6b;font:bold 18px monospace;";
+    overlay.append(title);
+
+    for (const problem of JSON.parse(e.data)) {
+      if (problem.file) {
+        const location = document.createElement("div");
+        location.textContent = problem.line ? `${problem.file}:${problem.line}` : problem.file;
+        location.style.cssText = "color:#ffd479;font-weight:bold;";
+        overlay.append(location);
+      }
+      const message = document.createElement("pre");
+      message.textContent = problem.message;
+      message.style.cssText = "margin:0.25em 0 1.5em;white-space:pre-wrap;";
+      overlay.append(message);
+    }
+
+    document.body.append(overlay);
+  });
+  events.addEventListener("css", (e) => {
+    clearErrors();
+    const paths = JSON.parse(e.data);
+    for (const link of document.querySelectorAll('link[rel="stylesheet"]')) {
+      const url = new URL(link.href, location.href);
+      if (!paths.some((path) => url.pathname.endsWith(path))) {
+        continue;
+      }
+      // Swap in a new link once loaded, so the page isn't left unstyled.
+      url.searchParams.set("tars", Date.now());
+      const next = link.cloneNode();
+      next.href = url.href;
+      next.onload = () => link.remove();
+      link.after(next);
+    }
+  });
+})();
+</script>
+"#;
+
+/// A message to connected pages, after the site is rebuilt.
+#[derive(Clone, Debug)]
+enum Reload {
+    /// Reload the page.
+    Page,
+    /// Reload stylesheets at these URL paths.
+    Styles(Vec<String>),
+}
+
+impl Reload {
+    /// Returns the message for the changes a rebuild was for.
+    ///
+    /// Stylesheets are reloaded in place when only static CSS files changed.
+    fn new(config: &Config, changes: &Changes) -> Self {
+        let only_css = !changes.full
+            && !changes.config
+            && changes.content.is_empty()
+            && changes.templates.is_empty()
+            && !changes.statics.is_empty()
+            && changes
+                .statics
+                .iter()
+                .all(|path| path.extension().is_some_and(|ext| ext == "css"));
+
+        if !only_css {
+            return Self::Page;
+        }
+
+        let static_prefix = Path::new(&config.build.static_prefix);
+        let paths = changes
+            .statics
+            .iter()
+            .map(|path| {
+                let path = static_prefix.join(path);
+                let parts: Vec<_> = path
+                    .iter()
+                    .filter(|c| *c != "/")
+                    .map(|c| c.to_string_lossy())
+                    .collect();
+                format!("/{}", parts.join("/"))
+            })
+            .collect();
+
+        Self::Styles(paths)
+    }
+
+    fn event(&self) -> warp::sse::Event {
+        match self {
+            Self::Page => warp::sse::Event::default().event("reload").data(""),
+            Self::Styles(paths) => warp::sse::Event::default()
+                .event("css")
+                .data(serde_json::to_string(paths).unwrap_or_default()),
+        }
+    }
+}
+
+/// A problem reported by a failed build, shown in the browser.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct BuildProblem {
+    file: Option<String>,
+    line: Option<usize>,
+    message: String,
+}
+
+impl BuildProblem {
+    /// Returns the problems reported by a build error, one for each page which
+    /// failed.
+    fn from_error(err: &anyhow::Error) -> Vec<Self> {
+        match err.downcast_ref::<PageErrors>() {
+            Some(PageErrors(errors)) => errors
+                .iter()
+                .map(|(file, e)| Self::new(Some(file.clone()), e))
+                .collect(),
+            None => vec![Self::new(None, err)],
+        }
+    }
+
+    /// Returns the problem reported by `err`, located by the innermost
+    /// template or configuration error in its chain.
+    fn new(file: Option<String>, err: &anyhow::Error) -> Self {
+        let mut problem = Self {
+            file,
+            line: None,
+            message: err.to_string(),
+        };
+
+        for cause in err.chain() {
+            if let Some(e) = cause.downcast_ref::<minijinja::Error>()
+                && let Some(name) = e.name()
+            {
+                problem.file = Some(name.to_string());
+                problem.line = e.line();
+            } else if let Some(e) = cause.downcast_ref::<ParseError>() {
+                problem.file = Some(e.path.display().to_string());
+                problem.line = e.line;
+            }
         }
 
-        for res in notify_rx {
-            match res {
-                Ok(event) => match event.kind {
-                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
-                        println!("Rebuilding...");
-                        if let Err(e) = builder.rebuild() {
-                            println!("Build error: {e}");
+        problem
+    }
+}
+
+/// Returns the event reporting the build status to connected pages.
+fn status_event(problems: &[BuildProblem]) -> warp::sse::Event {
+    if problems.is_empty() {
+        warp::sse::Event::default().event("ok").data("")
+    } else {
+        warp::sse::Event::default()
+            .event("failed")
+            .data(serde_json::to_string(problems).unwrap_or_default())
+    }
+}
+
+/// Channels which notify connected pages of builds.
+struct BuildEvents {
+    refresh_tx: broadcast::Sender<Reload>,
+    status_tx: watch::Sender<Vec<BuildProblem>>,
+}
+
+impl BuildEvents {
+    /// Report the result of a build.
+    ///
+    /// Pages are reloaded after successful builds, and keep showing the
+    /// previous build along with the errors otherwise.
+    fn report(&self, result: Result<()>, reload: Reload) {
+        let problems = match result {
+            Ok(()) => {
+                let _ = self.refresh_tx.send(reload);
+                Vec::new()
+            }
+            Err(e) => {
+                println!("Build error: {e}");
+                BuildProblem::from_error(&e)
+            }
+        };
+
+        self.show(problems);
+    }
+
+    /// Report a configuration file which failed to load, in place of the
+    /// result of a build.
+    fn report_config(&self, path: &str, err: &anyhow::Error) {
+        println!("Failed to load config file {path}: {err}");
+
+        let mut problems = BuildProblem::from_error(err);
+        for problem in &mut problems {
+            problem.file.get_or_insert_with(|| path.to_string());
+        }
+
+        self.show(problems);
+    }
+
+    fn show(&self, problems: Vec<BuildProblem>) {
+        self.status_tx.send_if_modified(|current| {
+            let modified = *current != problems;
+            *current = problems;
+            modified
+        });
+    }
+}
+
+/// Insert the reload client before the last closing body tag of a page, or
+/// its head if it has no body.
+///
+/// Returns `None` for fragments and other HTML outputs with neither tag, which
+/// are served unchanged.
+fn inject_reload(html: &[u8]) -> Option<Vec<u8>> {
+    let rfind = |tag: &[u8]| {
+        html.windows(tag.len())
+            .rposition(|w| w.eq_ignore_ascii_case(tag))
+    };
+    let at = rfind(b"</body>").or_else(|| rfind(b"</head>"))?;
+
+    let mut injected = Vec::with_capacity(html.len() + RELOAD_JS.len());
+    injected.extend_from_slice(&html[..at]);
+    injected.extend_from_slice(RELOAD_JS.as_bytes());
+    injected.extend_from_slice(&html[at..]);
+
+    Some(injected)
+}
+
+/// Configuration the listener is started with, which restarts it when changed.
+#[derive(Clone, PartialEq)]
+struct Listener {
+    addr: SocketAddr,
+    auto_reload: bool,
+}
+
+impl Listener {
+    fn new(config: &Config) -> Result<Self> {
+        let socket_str = format!("{}:{}", config.serve.host, config.serve.port);
+
+        Ok(Self {
+            addr: socket_str.parse()?,
+            auto_reload: config.serve.auto_reload,
+        })
+    }
+}
+
+/// Paths watched for changes, for the current configuration.
+#[derive(Default)]
+struct Watched {
+    /// Source directories, watched recursively.
+    dirs: Vec<PathBuf>,
+    /// Configuration files, watched through their parent directories, since
+    /// editors often replace files rather than writing to them.
+    files: Vec<PathBuf>,
+    /// Directories passed to the watcher.
+    watching: Vec<PathBuf>,
+}
+
+impl Watched {
+    fn new(config: &Config) -> Self {
+        let roots = config.roots();
+
+        let mut dirs = vec![roots.content];
+        dirs.extend(roots.templates);
+        dirs.extend(roots.statics);
+        dirs.extend([
+            roots.data,
+            roots.i18n,
+            config.project_path(&config.build.plugin_dir),
+        ]);
+
+        Self {
+            dirs: dirs.iter().filter_map(|dir| absolute(dir).ok()).collect(),
+            files: config
+                .files()
+                .iter()
+                .filter_map(|file| absolute(file).ok())
+                .collect(),
+            watching: Vec::new(),
+        }
+    }
+
+    /// Watch the paths, replacing the paths watched by `prev`.
+    fn watch(&mut self, watcher: &mut RecommendedWatcher, prev: &Watched) {
+        for path in &prev.watching {
+            let _ = watcher.unwatch(path);
+        }
+
+        // Directories which don't exist yet aren't watched.
+        for dir in self.dirs.iter().filter(|dir| dir.is_dir()) {
+            match watcher.watch(dir, RecursiveMode::Recursive) {
+                Ok(()) => self.watching.push(dir.clone()),
+                Err(e) => println!("Failed to watch {}: {e}", dir.display()),
+            }
+        }
+
+        for file in &self.files {
+            let Some(parent) = file.parent().filter(|p| p.is_dir()) else {
+                continue;
+            };
+            if self.watching.iter().any(|p| p == parent) {
+                continue;
+            }
+
+            match watcher.watch(parent, RecursiveMode::NonRecursive) {
+                Ok(()) => self.watching.push(parent.to_path_buf()),
+                Err(e) => println!("Failed to watch {}: {e}", parent.display()),
+            }
+        }
+    }
+
+    /// Record a changed path in `changes`, unless it isn't a watched file.
+    fn classify(&self, config: &Config, changes: &mut Changes, path: &Path) {
+        let Ok(path) = absolute(path) else {
+            return;
+        };
+
+        if self.files.contains(&path) {
+            changes.config = true;
+        } else if self.dirs.iter().any(|dir| path.starts_with(dir)) {
+            changes.add(&config.roots(), &path);
+        }
+    }
+}
+
+fn ignore_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+fn is_ignored(config: &Config, ignore: &GlobSet, path: &Path) -> bool {
+    if path.file_name().is_some_and(|name| ignore.is_match(name)) {
+        return true;
+    }
+
+    // The root is empty for configuration files in the working directory.
+    let root = Path::new(".").join(&config.root);
+    match (absolute(path), absolute(root)) {
+        (Ok(path), Ok(root)) => ignore.is_match(path.strip_prefix(root).unwrap_or(&path)),
+        _ => false,
+    }
+}
+
+/// Wait for file changes, and collect them until none are seen for the
+/// debounce window.
+///
+/// Returns `None` once the watcher stops.
+fn next_changes(
+    rx: &Receiver<notify::Result<Event>>,
+    config: &Config,
+    watched: &Watched,
+    ignore: &GlobSet,
+) -> Option<Changes> {
+    let window = Duration::from_millis(config.serve.debounce_ms);
+    let mut changes = Changes::default();
+    let mut res = rx.recv().ok()?;
+
+    loop {
+        match res {
+            Ok(event) => match event.kind {
+                EventKind::Modify(ModifyKind::Metadata(_)) => {}
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+                    for path in &event.paths {
+                        if !is_ignored(config, ignore, path) {
+                            watched.classify(config, &mut changes, path);
                         }
-                        let _ = refresh_tx.send(());
                     }
-                    _ => {}
-                },
-                Err(e) => {
-                    println!("Watch error: {e}");
                 }
+                _ => {}
+            },
+            Err(e) => {
+                println!("Watch error: {e}");
             }
         }
-    });
 
-    println!("Running server on http://{socket_addr}");
+        res = match rx.recv_timeout(window) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) if changes.is_empty() => rx.recv().ok()?,
+            Err(RecvTimeoutError::Timeout) => return Some(changes),
+            Err(RecvTimeoutError::Disconnected) => return None,
+        };
+    }
+}
+
+/// Build the site, then rebuild it as files change until the watcher stops.
+///
+/// When a configuration file changes, the configuration is loaded again, and
+/// the listener restarted if its settings changed. Other changes are ignored
+/// while the configuration fails to load.
+fn watch_and_build(
+    mut config: Config,
+    options: LoadOptions,
+    mut watcher: RecommendedWatcher,
+    rx: Receiver<notify::Result<Event>>,
+    output: Arc<MemoryOutput>,
+    events: BuildEvents,
+    listener_tx: watch::Sender<Listener>,
+) {
+    let mut watched = Watched::default();
+
+    loop {
+        let ignore = ignore_set(&config.serve.ignore).unwrap_or_else(|e| {
+            println!("Invalid ignore pattern: {e}");
+            GlobSet::empty()
+        });
+
+        let mut next_watched = Watched::new(&config);
+        next_watched.watch(&mut watcher, &watched);
+        watched = next_watched;
+
+        let mut builder = Builder::in_memory(&config, output.clone());
+        println!("Building...");
+        events.report(builder.build(), Reload::Page);
+
+        loop {
+            let Some(changes) = next_changes(&rx, &config, &watched, &ignore) else {
+                return;
+            };
+
+            if changes.config {
+                break;
+            }
+
+            println!("Rebuilding...");
+            events.report(builder.update(&changes), Reload::new(&config, &changes));
+        }
+
+        drop(builder);
 
-    if auto_reload {
+        // Until the configuration loads again, keep serving the last build
+        // and showing the error, rather than rebuilding with the old one.
+        let new_config = loop {
+            println!("Reloading configuration...");
+            match Config::from_file(&config.path, &options) {
+                Ok(new_config) => break new_config,
+                Err(e) => events.report_config(&config.path, &e),
+            }
+
+            loop {
+                let Some(changes) = next_changes(&rx, &config, &watched, &ignore) else {
+                    return;
+                };
+
+                if changes.config {
+                    break;
+                }
+            }
+        };
+
+        for warning in &new_config.warnings {
+            println!("Warning: {warning}");
+        }
+
+        match Listener::new(&new_config) {
+            Ok(listener) => {
+                listener_tx.send_if_modified(|current| {
+                    let modified = *current != listener;
+                    *current = listener;
+                    modified
+                });
+            }
+            Err(e) => println!("Invalid server address: {e}"),
+        }
+
+        config = new_config;
+    }
+}
+
+/// Serve a generated file from memory, or a directory's index page.
+///
+/// The reload client is injected into HTML pages when `auto_reload` is set.
+async fn serve_file(
+    output: Arc<MemoryOutput>,
+    auto_reload: bool,
+    tail: Tail,
+) -> Result<impl warp::Reply, Rejection> {
+    let path = percent_encoding::percent_decode_str(tail.as_str())
+        .decode_utf8()
+        .map_err(|_| warp::reject::not_found())?;
+    let path = Path::new(path.as_ref());
+
+    let candidates = if path.as_os_str().is_empty() || tail.as_str().ends_with('/') {
+        vec![path.join("index.html")]
+    } else {
+        vec![path.to_path_buf(), path.join("index.html")]
+    };
+
+    for path in candidates {
+        let contents = match output.get(&path) {
+            Some(OutputFile::Data(data)) => data,
+            Some(OutputFile::Path(src_path)) => match tokio::fs::read(src_path).await {
+                Ok(data) => data.into(),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+        let injected = match auto_reload && mime == mime_guess::mime::TEXT_HTML {
+            true => inject_reload(&contents),
+            false => None,
+        };
+        let contents = injected.map_or(contents, Into::into);
+
+        return Ok(warp::http::Response::builder()
+            .header(CONTENT_TYPE, mime.as_ref())
+            .body(contents));
+    }
+
+    // Until a build succeeds, pages show its errors and reload once it does.
+    if output.is_empty() {
+        let page = match auto_reload {
+            true => inject_reload(WAITING_HTML.as_bytes()),
+            false => None,
+        };
+        let page = page.unwrap_or_else(|| WAITING_HTML.as_bytes().to_vec());
+
+        return Ok(warp::http::Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(page.into()));
+    }
+
+    Err(warp::reject::not_found())
+}
+
+/// Returns the filter serving generated files from memory.
+fn files(
+    output: Arc<MemoryOutput>,
+    auto_reload: bool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::any().map(move || output.clone()))
+        .and(warp::any().map(move || auto_reload))
+        .and(warp::path::tail())
+        .and_then(serve_file)
+}
+
+/// Serve the generated site until the listener's settings change.
+async fn listen(
+    listener: Listener,
+    output: Arc<MemoryOutput>,
+    refresh_tx: broadcast::Sender<Reload>,
+    status_rx: watch::Receiver<Vec<BuildProblem>>,
+    mut listener_rx: watch::Receiver<Listener>,
+) -> Result<()> {
+    let socket = tokio::net::TcpListener::bind(listener.addr).await?;
+    println!("Running server on http://{}", listener.addr);
+
+    let files = files(output, listener.auto_reload);
+
+    let server = if listener.auto_reload {
+        let sse_listener_rx = listener_rx.clone();
         let sse = warp::path("__tars_reload__").and(warp::get()).map(move || {
-            let mut rx = refresh_sse.subscribe();
+            let mut rx = refresh_tx.subscribe();
+            let mut status_rx = status_rx.clone();
+            let mut listener_rx = sse_listener_rx.clone();
 
+            // Streams end when the listener restarts, so they aren't left open.
             let stream = async_stream::stream! {
+                // Pages opened while the build is failing show its errors.
+                let problems = status_rx.borrow_and_update().clone();
+                if !problems.is_empty() {
+                    yield Ok::<_, Infallible>(status_event(&problems));
+                }
+
                 loop {
-                    if rx.recv().await.is_ok() {
-                        yield Ok::<_, Infallible>(
-                            warp::sse::Event::default().data("reload")
-                        );
+                    tokio::select! {
+                        res = rx.recv() => if let Ok(reload) = res {
+                            yield Ok::<_, Infallible>(reload.event());
+                        },
+                        res = status_rx.changed() => {
+                            if res.is_err() {
+                                break;
+                            }
+                            let problems = status_rx.borrow_and_update().clone();
+                            yield Ok::<_, Infallible>(status_event(&problems));
+                        },
+                        _ = listener_rx.changed() => break,
                     }
                 }
             };
@@ -83,12 +631,364 @@ pub async fn run_server(config: Arc<Config>) -> Result<()> {
             warp::sse::reply(warp::sse::keep_alive().stream(stream))
         });
 
-        let routes = warp::fs::dir(build_dir).or(sse);
-        warp::serve(routes).run(socket_addr).await;
+        tokio::spawn(warp::serve(sse.or(files)).incoming(socket).run())
     } else {
-        let routes = warp::fs::dir(build_dir);
-        warp::serve(routes).run(socket_addr).await;
+        tokio::spawn(warp::serve(files).incoming(socket).run())
     };
 
+    let _ = listener_rx.changed().await;
+
+    server.abort();
+    let _ = server.await;
+
+    Ok(())
+}
+
+pub async fn run_server(config: Config, options: LoadOptions) -> Result<()> {
+    let (refresh_tx, _) = broadcast::channel::<Reload>(16);
+    let (status_tx, status_rx) = watch::channel(Vec::new());
+    let (listener_tx, mut listener_rx) = watch::channel(Listener::new(&config)?);
+    let (notify_tx, notify_rx) = mpsc::channel::<notify::Result<Event>>();
+    let watcher = notify::recommended_watcher(notify_tx)?;
+
+    // The site is generated in memory, leaving the build directory untouched.
+    let output = Arc::new(MemoryOutput::default());
+
+    let events = BuildEvents {
+        refresh_tx: refresh_tx.clone(),
+        status_tx,
+    };
+    let build_output = output.clone();
+    std::thread::spawn(move || {
+        watch_and_build(
+            config,
+            options,
+            watcher,
+            notify_rx,
+            build_output,
+            events,
+            listener_tx,
+        );
+    });
+
+    let mut started = false;
+
+    loop {
+        let listener = listener_rx.borrow_and_update().clone();
+        let addr = listener.addr;
+
+        match listen(
+            listener,
+            output.clone(),
+            refresh_tx.clone(),
+            status_rx.clone(),
+            listener_rx.clone(),
+        )
+        .await
+        {
+            // Fail if the server can't start at all.
+            Err(e) if !started => return Err(e),
+            Err(e) => {
+                println!("Failed to listen on {addr}: {e}");
+
+                // Wait for the configuration to change before trying again.
+                if listener_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+            // The listener stops when its settings change, or the watcher stops.
+            Ok(()) if !listener_rx.has_changed().unwrap_or(false) => break,
+            Ok(()) => {}
+        }
+
+        started = true;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::fs::{create_dir_all, write};
+    use tempfile::{TempDir, tempdir};
+    use warp::hyper::body::Bytes;
+
+    /// Write a project with `files` to a temporary directory.
+    fn write_project(files: &[(&str, &str)]) -> TempDir {
+        let root = tempdir().unwrap();
+        for (path, contents) in files {
+            let path = root.path().join(path);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, contents).unwrap();
+        }
+
+        root
+    }
+
+    fn load_config(root: &TempDir) -> Result<Config> {
+        let config_path = root.path().join("tars.toml");
+        Config::from_file(config_path.to_str().unwrap(), &LoadOptions::default())
+    }
+
+    /// Returns the problems reported by building a project with `files`.
+    fn build_problems(files: &[(&str, &str)]) -> Vec<BuildProblem> {
+        let root = write_project(files);
+        let result = load_config(&root)
+            .and_then(|config| Builder::in_memory(&config, Arc::default()).build());
+
+        BuildProblem::from_error(&result.unwrap_err())
+    }
+
+    fn problem(file: &str, line: usize) -> (Option<String>, Option<usize>) {
+        (Some(file.to_string()), Some(line))
+    }
+
+    fn locations(problems: &[BuildProblem]) -> Vec<(Option<String>, Option<usize>)> {
+        problems.iter().map(|p| (p.file.clone(), p.line)).collect()
+    }
+
+    const CONFIG: &str = "[site]\ntitle = \"Test\"\n";
+
+    fn event(kind: EventKind, path: &Path) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(path.to_path_buf()))
+    }
+
+    #[test]
+    fn test_ignore_set() {
+        let root = write_project(&[(
+            "tars.toml",
+            "[serve]\nignore = [\"*.swp\", \"content/drafts/**\"]\n",
+        )]);
+        let config = load_config(&root).unwrap();
+        let ignore = ignore_set(&config.serve.ignore).unwrap();
+        let ignored = |path: &str| is_ignored(&config, &ignore, &root.path().join(path));
+
+        assert!(ignored("content/.post.md.swp"));
+        assert!(ignored("template/.page.html.swp"));
+        assert!(ignored("content/drafts/post.md"));
+        assert!(!ignored("content/post.md"));
+        assert!(!ignored("template/drafts/post.md"));
+
+        assert!(ignore_set(&["[unclosed".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_next_changes() {
+        let root = write_project(&[
+            ("tars.toml", CONFIG),
+            ("content/post.md", "Post\n"),
+            ("template/page.html", ""),
+        ]);
+        let config = load_config(&root).unwrap();
+        let ignore = ignore_set(&config.serve.ignore).unwrap();
+        let watched = Watched::new(&config);
+        let path = |path: &str| root.path().join(path);
+
+        let (tx, rx) = mpsc::channel();
+        let modify = EventKind::Modify(ModifyKind::Any);
+        tx.send(event(modify, &path("content/post.md"))).unwrap();
+        tx.send(event(modify, &path("content/.post.md.swp")))
+            .unwrap();
+        tx.send(event(modify, &path("elsewhere/file.md"))).unwrap();
+        tx.send(event(
+            EventKind::Modify(ModifyKind::Metadata(notify::event::MetadataKind::Any)),
+            &path("template/page.html"),
+        ))
+        .unwrap();
+        tx.send(Err(notify::Error::generic("lost events"))).unwrap();
+        tx.send(event(modify, &path("tars.toml"))).unwrap();
+
+        // Changes are collected until none are seen for the debounce window.
+        let changes = next_changes(&rx, &config, &watched, &ignore).unwrap();
+        assert_eq!(changes.content, BTreeSet::from([PathBuf::from("post.md")]));
+        assert!(changes.templates.is_empty());
+        assert!(changes.statics.is_empty());
+        assert!(changes.config && !changes.full);
+
+        drop(tx);
+        assert!(next_changes(&rx, &config, &watched, &ignore).is_none());
+    }
+
+    #[test]
+    fn test_template_problems() {
+        let problems = build_problems(&[
+            ("tars.toml", CONFIG),
+            ("content/a.md", "---\ntemplate: page.html\n---\nA\n"),
+            ("content/b.md", "---\ntemplate: other.html\n---\nB\n"),
+            (
+                "template/page.html",
+                "<h1>\n{% include \"nav.html\" %}\n</h1>",
+            ),
+            ("template/nav.html", "<nav>\n\n{{ 1 + }}</nav>"),
+            ("template/other.html", "{{ missing_filter | nope }}"),
+        ]);
+
+        assert_eq!(
+            locations(&problems),
+            [problem("nav.html", 3), problem("other.html", 1)]
+        );
+        assert!(problems[0].message.starts_with("template error:"));
+    }
+
+    #[test]
+    fn test_config_problems() {
+        let problems = build_problems(&[("tars.toml", "[site]\ntitle = \"Test\"\n\n[build\n")]);
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(4));
+        assert!(problems[0].file.as_ref().unwrap().ends_with("tars.toml"));
+    }
+
+    fn inject(html: &str) -> Option<String> {
+        inject_reload(html.as_bytes()).map(|page| String::from_utf8(page).unwrap())
+    }
+
+    #[test]
+    fn test_inject_reload_before_last_body() {
+        let page = "<html><head></head><body><script>let s = '</body>';</script></BODY></html>";
+        let expected = format!(
+            "<html><head></head><body><script>let s = '</body>';</script>{RELOAD_JS}</BODY></html>"
+        );
+        assert_eq!(inject(page), Some(expected));
+    }
+
+    #[test]
+    fn test_inject_reload_head_only() {
+        let page = "<HTML><HEAD><title>x</title></HEAD></HTML>";
+        let expected = format!("<HTML><HEAD><title>x</title>{RELOAD_JS}</HEAD></HTML>");
+        assert_eq!(inject(page), Some(expected));
+    }
+
+    #[test]
+    fn test_inject_reload_skips_fragments() {
+        assert_eq!(inject("<li><a href=\"/\">Home</a></li>"), None);
+        assert_eq!(inject(""), None);
+    }
+
+    #[test]
+    fn test_update_problems() {
+        let root = write_project(&[
+            ("tars.toml", CONFIG),
+            ("content/a.md", "---\ntemplate: page.html\n---\nA\n"),
+            ("content/b.md", "B\n"),
+            ("template/page.html", "{{ content }}"),
+        ]);
+        let config = load_config(&root).unwrap();
+        let mut builder = Builder::in_memory(&config, Arc::default());
+        builder.build().unwrap();
+
+        write(
+            root.path().join("template/page.html"),
+            "\n{{ content | nope }}",
+        )
+        .unwrap();
+        let changes = Changes {
+            templates: BTreeSet::from(["page.html".to_string()]),
+            ..Default::default()
+        };
+        let problems = BuildProblem::from_error(&builder.update(&changes).unwrap_err());
+        assert_eq!(locations(&problems), [problem("page.html", 2)]);
+
+        write(root.path().join("template/page.html"), "{{ content }}").unwrap();
+        write(root.path().join("content/b.md"), b"\xff\xfe").unwrap();
+        let changes = Changes {
+            content: BTreeSet::from([PathBuf::from("b.md")]),
+            ..Default::default()
+        };
+        let problems = BuildProblem::from_error(&builder.update(&changes).unwrap_err());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].file.as_ref().unwrap().ends_with("b.md"));
+    }
+
+    async fn get(output: &Arc<MemoryOutput>, path: &str) -> warp::http::Response<Bytes> {
+        warp::test::request()
+            .path(path)
+            .reply(&files(output.clone(), true))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_serve_before_first_build() {
+        let output = Arc::new(MemoryOutput::default());
+
+        let res = get(&output, "/").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(
+            res.body()
+                .ends_with(format!("{RELOAD_JS}</body>\n</html>\n").as_bytes())
+        );
+
+        output.insert(
+            PathBuf::from("index.html"),
+            OutputFile::Data("<body>Home</body>".into()),
+        );
+        assert_eq!(get(&output, "/").await.status(), StatusCode::OK);
+        assert_eq!(
+            get(&output, "/missing.html").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_from_memory() {
+        let dir = tempdir().unwrap();
+        let css = dir.path().join("site.css");
+        write(&css, "body {}").unwrap();
+
+        let output = Arc::new(MemoryOutput::default());
+        output.insert(
+            PathBuf::from("docs/index.html"),
+            OutputFile::Data("<body>Docs</body>".into()),
+        );
+        output.insert(
+            PathBuf::from("nav.html"),
+            OutputFile::Data("<li>Nav</li>".into()),
+        );
+        output.insert(PathBuf::from("a b.css"), OutputFile::Path(css));
+
+        let res = get(&output, "/docs/").await;
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html");
+        assert_eq!(
+            res.body(),
+            format!("<body>Docs{RELOAD_JS}</body>").as_bytes()
+        );
+        assert_eq!(get(&output, "/docs").await.status(), StatusCode::OK);
+
+        assert_eq!(get(&output, "/nav.html").await.body(), "<li>Nav</li>");
+
+        let res = get(&output, "/a%20b.css").await;
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+        assert_eq!(res.body(), "body {}");
+    }
+
+    #[test]
+    fn test_report_config_problems() {
+        let (refresh_tx, _) = broadcast::channel(1);
+        let (status_tx, status_rx) = watch::channel(Vec::new());
+        let events = BuildEvents {
+            refresh_tx,
+            status_tx,
+        };
+
+        let root = write_project(&[("tars.toml", "[site]\ntitle = \"Test\"\n[build\n")]);
+        let err = load_config(&root).unwrap_err();
+        events.report_config("tars.toml", &err);
+
+        let problems = status_rx.borrow().clone();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(3));
+        assert!(problems[0].file.as_ref().unwrap().ends_with("tars.toml"));
+
+        // Errors without a location are reported against the config file.
+        events.report_config("tars.toml", &anyhow::anyhow!("environment 'x' not found"));
+        assert_eq!(
+            locations(&status_rx.borrow()),
+            [(Some("tars.toml".to_string()), None)]
+        );
+
+        events.report(Ok(()), Reload::Page);
+        assert!(status_rx.borrow().is_empty());
+    }
+}
@@ -1,40 +1,120 @@
 use anyhow::Result;
-use minijinja::value::Value;
+use minijinja::{
+    Error, ErrorKind, State,
+    machinery::{
+        WhitespaceConfig,
+        ast::{Expr, Stmt},
+        parse,
+    },
+    path_loader,
+    syntax::SyntaxConfig,
+    value::{Kwargs, Value},
+};
 use serde::Serialize;
 use std::{
-    fs,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Mutex,
 };
-use walkdir::WalkDir;
 
 use crate::{
-    config::Config,
+    config::{Config, Language, Roots, Site},
+    data::load_data_dir,
+    filters,
+    i18n::Catalogs,
     markdown::{FrontMatter, Page},
+    menu::{MenuItem, Menus},
 };
 
+/// Built-in template used when the default template doesn't exist.
+pub const DEFAULT_TEMPLATE: &str = include_str!("../starters/default/template/default.html");
+
+#[derive(Serialize)]
+pub struct Translation<'a> {
+    pub lang: &'a str,
+    pub title: Option<&'a String>,
+    pub path: &'a PathBuf,
+    pub url: String,
+}
+
 #[derive(Serialize)]
 pub struct TemplateContext<'a> {
     pub pages: &'a [Page],
     pub meta: &'a FrontMatter,
     pub content: &'a String,
     pub path: &'a PathBuf,
+    pub menus: HashMap<String, Vec<MenuItem>>,
+    pub lang: Option<&'a String>,
+    pub translations: Vec<Translation<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'a Language>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<Site>,
 }
 
 impl<'a> TemplateContext<'a> {
-    /// Create a new `TemplateContext` object given a page list
-    /// and current page.
-    pub fn new(pages: &'a Vec<Page>, page: &'a Page) -> Self {
+    /// Create a new `TemplateContext` object given a page list,
+    /// current page, and resolved site menus.
+    ///
+    /// For multilingual sites, `site` is overridden with the settings for
+    /// the page's language, and `translations` lists other versions of the page.
+    pub fn new(config: &'a Config, pages: &'a Vec<Page>, page: &'a Page, menus: &Menus) -> Self {
+        let language = page.lang.as_ref().and_then(|code| config.language(code));
+
+        let translations = pages
+            .iter()
+            .filter(|p| p.translation_key == page.translation_key && p.lang != page.lang)
+            .filter_map(|p| {
+                Some(Translation {
+                    lang: p.lang.as_ref()?,
+                    title: p.meta.title.as_ref(),
+                    path: &p.rel_path,
+                    url: p.url(),
+                })
+            })
+            .collect();
+
         Self {
             pages,
             meta: &page.meta,
             content: &page.content,
             path: &page.rel_path,
+            menus: menus.for_page(page),
+            lang: page.lang.as_ref(),
+            translations,
+            language,
+            site: language.map(|l| config.site_for(l)),
         }
     }
 }
 
+/// Inputs a template depends on, including templates it extends, includes
+/// or imports.
+#[derive(Clone, Default)]
+pub struct TemplateDeps {
+    /// Source hash of each template used, or an empty string if missing.
+    pub templates: BTreeMap<String, String>,
+    /// Whether the templates read other pages, with `pages`, `get_page` or
+    /// `get_section`.
+    pub pages: bool,
+    /// Whether the templates reference static files with `asset_url`.
+    pub assets: bool,
+    /// Whether the output may differ between builds with the same inputs:
+    /// a template is loaded by a name only known when rendering, or the
+    /// current time is read with `now`.
+    pub dynamic: bool,
+}
+
 pub struct TemplateEnvironment<'a> {
     env: minijinja::Environment<'a>,
+    /// Dependencies of each template rendered since templates were loaded.
+    deps: Mutex<HashMap<String, TemplateDeps>>,
+}
+
+impl Default for TemplateEnvironment<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> TemplateEnvironment<'a> {
@@ -42,49 +122,410 @@ impl<'a> TemplateEnvironment<'a> {
     pub fn new() -> Self {
         Self {
             env: minijinja::Environment::new(),
+            deps: Mutex::default(),
         }
     }
 
-    /// Load all templates from the templates directory.
+    /// Set up template loading from the configured template directories.
     ///
-    /// This function succeeds if all templates are valid, or if the `templates`
-    /// directory doesn't exist.
-    pub fn load_templates(&mut self, config: &Config) -> Result<()> {
-        let tmpl_root = Path::new(&config.build.template_dir);
-
-        // If the templates directory doesn't exist, don't try to load anything.
-        if !tmpl_root.is_dir() {
-            return Ok(());
-        }
-
-        for entry in WalkDir::new(tmpl_root) {
-            let entry = entry?;
-            let path = entry.path();
+    /// Templates are loaded lazily by name, searching each directory in
+    /// order, so later directories act as fallbacks for earlier ones.
+    /// Directories which don't exist are skipped.
+    pub fn load_templates(&mut self, config: &Config, roots: &Roots) -> Result<()> {
+        self.deps.get_mut().unwrap().clear();
 
-            // Get the path inside `templates`
-            let name = path.strip_prefix(tmpl_root)?.to_string_lossy().into_owned();
+        let loaders: Vec<_> = roots
+            .templates
+            .iter()
+            .filter(|dir| dir.is_dir())
+            .inspect(|dir| println!("Using templates from {}", dir.display()))
+            .map(path_loader)
+            .collect();
 
-            if path.is_file() {
-                let tmpl_str = fs::read_to_string(path)?;
+        // The default templates for the site and each language fall back to
+        // the built-in template, so new projects build without any templates.
+        let mut default_names = vec![config.site.default_template.clone()];
+        for lang in &config.languages {
+            default_names.push(config.site_for(lang).default_template);
+        }
 
-                self.env.add_template_owned(name.clone(), tmpl_str)?;
+        self.env.set_loader(move |name| {
+            for loader in &loaders {
+                if let Some(source) = loader(name)? {
+                    return Ok(Some(source));
+                }
+            }
 
-                println!("Loaded template {name}");
+            if default_names.iter().any(|n| n == name) {
+                return Ok(Some(DEFAULT_TEMPLATE.to_string()));
             }
-        }
+
+            Ok(None)
+        });
 
         self.env
             .add_global("site", Value::from_serialize(&config.site));
         self.env
             .add_global("extra", Value::from_serialize(&config.extra));
+        self.env.add_global(
+            "tars",
+            minijinja::context! {
+                env => config.env,
+                version => env!("CARGO_PKG_VERSION"),
+            },
+        );
+
+        let data = load_data_dir(&roots.data)?;
+        self.env.add_global("data", Value::from_serialize(&data));
+
+        filters::register(&mut self.env, config, roots);
+
+        let catalogs = Catalogs::load(config, &roots.i18n)?;
+        self.env.add_function(
+            "trans",
+            move |state: &State, key: &str, kwargs: Kwargs| -> Result<String, Error> {
+                trans(&catalogs, state, key, kwargs)
+            },
+        );
 
         Ok(())
     }
 
+    /// Discard loaded templates, so they're loaded again when next used.
+    pub fn clear_templates(&mut self) {
+        self.env.clear_templates();
+        self.deps.get_mut().unwrap().clear();
+    }
+
+    /// Returns the dependencies of the template `name`.
+    ///
+    /// They're found once for each template, until templates are cleared.
+    pub fn dependencies(&self, name: &str) -> Result<TemplateDeps> {
+        if let Some(deps) = self.deps.lock().unwrap().get(name) {
+            return Ok(deps.clone());
+        }
+
+        let deps = self.find_dependencies(name)?;
+        self.deps
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), deps.clone());
+
+        Ok(deps)
+    }
+
+    fn find_dependencies(&self, name: &str) -> Result<TemplateDeps> {
+        let mut deps = TemplateDeps::default();
+        let mut pending = vec![name.to_string()];
+
+        while let Some(name) = pending.pop() {
+            if deps.templates.contains_key(&name) {
+                continue;
+            }
+
+            let tmpl = match self.env.get_template(&name) {
+                Ok(tmpl) => tmpl,
+                // Referenced templates may be optional, e.g. `ignore missing`.
+                Err(e) if e.kind() == ErrorKind::TemplateNotFound && !deps.templates.is_empty() => {
+                    deps.templates.insert(name, String::new());
+                    continue;
+                }
+                Err(e) => return Err(template_error(e)),
+            };
+
+            let vars = tmpl.undeclared_variables(false);
+            deps.pages |= ["pages", "get_page", "get_section"]
+                .iter()
+                .any(|v| vars.contains(*v));
+            deps.assets |= vars.contains("asset_url");
+            deps.dynamic |= vars.contains("now");
+
+            let (names, dynamic) = referenced_templates(&name, tmpl.source())?;
+            deps.dynamic |= dynamic;
+            pending.extend(names);
+
+            deps.templates.insert(name, sha256::digest(tmpl.source()));
+        }
+
+        Ok(deps)
+    }
+
     /// Render a template given context and name.
     pub fn render_template(&self, context: &TemplateContext, tmpl_name: &str) -> Result<String> {
-        let tmpl = self.env.get_template(tmpl_name)?;
-        let render_str = tmpl.render(context)?;
+        let tmpl = self.env.get_template(tmpl_name).map_err(template_error)?;
+        let render_str = tmpl.render(context).map_err(template_error)?;
         Ok(render_str)
     }
 }
+
+/// Returns the template names referenced by `extends`, `include`, `import`
+/// and `from` tags in the template `name`, and whether any tag names a
+/// template with an expression which is only known when rendering.
+fn referenced_templates(name: &str, source: &str) -> Result<(Vec<String>, bool)> {
+    let ast =
+        parse(source, name, SyntaxConfig, WhitespaceConfig::default()).map_err(template_error)?;
+
+    let mut refs = TemplateRefs::default();
+    refs.visit(&ast);
+
+    Ok((refs.names, refs.dynamic))
+}
+
+/// Template names collected from a parsed template.
+#[derive(Default)]
+struct TemplateRefs {
+    names: Vec<String>,
+    dynamic: bool,
+}
+
+impl TemplateRefs {
+    fn visit_all(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.visit(stmt);
+        }
+    }
+
+    fn visit(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Template(t) => self.visit_all(&t.children),
+            Stmt::ForLoop(f) => {
+                self.visit_all(&f.body);
+                self.visit_all(&f.else_body);
+            }
+            Stmt::IfCond(c) => {
+                self.visit_all(&c.true_body);
+                self.visit_all(&c.false_body);
+            }
+            Stmt::WithBlock(w) => self.visit_all(&w.body),
+            Stmt::SetBlock(s) => self.visit_all(&s.body),
+            Stmt::AutoEscape(a) => self.visit_all(&a.body),
+            Stmt::FilterBlock(f) => self.visit_all(&f.body),
+            Stmt::Block(b) => self.visit_all(&b.body),
+            Stmt::Macro(m) => self.visit_all(&m.body),
+            Stmt::CallBlock(c) => self.visit_all(&c.macro_decl.body),
+            Stmt::Extends(e) => self.add(&e.name),
+            Stmt::Include(i) => self.add(&i.name),
+            Stmt::Import(i) => self.add(&i.expr),
+            Stmt::FromImport(i) => self.add(&i.expr),
+            _ => {}
+        }
+    }
+
+    /// Add the templates named by `expr`: a string, a list of strings to
+    /// try in order, or a conditional choosing between them.
+    fn add(&mut self, expr: &Expr) {
+        if let Expr::IfExpr(e) = expr {
+            self.add(&e.true_expr);
+            if let Some(false_expr) = &e.false_expr {
+                self.add(false_expr);
+            }
+            return;
+        }
+
+        let Some(value) = expr.as_const() else {
+            self.dynamic = true;
+            return;
+        };
+
+        let names = match value.as_str() {
+            Some(name) => vec![Value::from(name)],
+            None => value.try_iter().map(Iterator::collect).unwrap_or_default(),
+        };
+        if names.is_empty() {
+            self.dynamic = true;
+        }
+
+        for name in names {
+            match name.as_str() {
+                Some(name) => self.names.push(name.to_string()),
+                None => self.dynamic = true,
+            }
+        }
+    }
+}
+
+/// Template function translating `key` into the current page's language.
+///
+/// The language may be overridden with a `lang` argument, and a `count`
+/// argument selects plural forms. All arguments are available as placeholders.
+fn trans(catalogs: &Catalogs, state: &State, key: &str, kwargs: Kwargs) -> Result<String, Error> {
+    let lang = match kwargs.get::<Option<String>>("lang")? {
+        Some(lang) => Some(lang),
+        None => state
+            .lookup("lang")
+            .and_then(|v| v.as_str().map(str::to_string)),
+    };
+    let count = kwargs.get::<Option<i64>>("count")?;
+
+    let mut args = HashMap::new();
+    for name in kwargs.args() {
+        let value: Value = kwargs.get(name)?;
+        args.insert(name.to_string(), value.to_string());
+    }
+
+    catalogs
+        .translate(lang.as_deref(), key, &args, count)
+        .map_err(|e| Error::new(ErrorKind::InvalidOperation, e.to_string()))
+}
+
+/// Convert a template error into an error reporting the template name and
+/// line of each error in the chain, e.g. from nested `include`s.
+///
+/// The template error is kept as the source, so callers can read its location.
+fn template_error(err: Error) -> anyhow::Error {
+    let mut lines = Vec::new();
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&err);
+
+    while let Some(e) = source {
+        let line = match e.downcast_ref::<Error>() {
+            Some(e) => {
+                let msg = match e.detail() {
+                    Some(detail) => format!("{}: {}", e.kind(), detail),
+                    None => e.kind().to_string(),
+                };
+
+                match (e.name(), e.line()) {
+                    (Some(name), Some(line)) => format!("{name}:{line}: {msg}"),
+                    (Some(name), None) => format!("{name}: {msg}"),
+                    _ => msg,
+                }
+            }
+            None => e.to_string(),
+        };
+
+        lines.push(line);
+        source = e.source();
+    }
+
+    let message = format!("template error: {}", lines.join("\n  caused by: "));
+    anyhow::Error::new(err).context(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refs(source: &str) -> (Vec<String>, bool) {
+        referenced_templates("test.html", source).unwrap()
+    }
+
+    fn names(names: &[&str]) -> (Vec<String>, bool) {
+        (names.iter().map(|n| n.to_string()).collect(), false)
+    }
+
+    fn write(path: &std::path::Path, source: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+
+    #[test]
+    fn test_load_templates_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let site = dir.path().join("template");
+        let theme = dir.path().join("theme");
+        write(&site.join("page.html"), r#"{% extends "base.html" %}"#);
+        write(&theme.join("page.html"), "theme page");
+        write(&theme.join("base.html"), "theme base");
+
+        let config: Config = toml::from_str("").unwrap();
+        let roots = Roots {
+            content: dir.path().join("content"),
+            templates: vec![dir.path().join("missing"), site, theme],
+            statics: vec![],
+            data: dir.path().join("data"),
+            i18n: dir.path().join("i18n"),
+        };
+        let mut env = TemplateEnvironment::new();
+        env.load_templates(&config, &roots).unwrap();
+
+        let render = |name: &str| env.env.get_template(name)?.render(());
+        assert_eq!(render("page.html").unwrap(), "theme base");
+        assert_eq!(render("base.html").unwrap(), "theme base");
+        assert_eq!(
+            env.env
+                .get_template(&config.site.default_template)
+                .unwrap()
+                .source(),
+            DEFAULT_TEMPLATE
+        );
+
+        let err = render("other.html").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TemplateNotFound);
+        let err = env.dependencies("other.html").err().unwrap();
+        assert!(err.to_string().starts_with("template error:"), "{err}");
+    }
+
+    #[test]
+    fn test_referenced_templates() {
+        assert_eq!(refs(r#"{% extends "base.html" %}"#), names(&["base.html"]));
+        assert_eq!(refs(r#"{% include 'nav.html' %}"#), names(&["nav.html"]));
+        assert_eq!(refs(r#"{%- include "nav.html" -%}"#), names(&["nav.html"]));
+        assert_eq!(refs(r#"{%+ include "nav.html" +%}"#), names(&["nav.html"]));
+        assert_eq!(
+            refs(r#"{% include ["a.html", "b.html"] ignore missing %}"#),
+            names(&["a.html", "b.html"])
+        );
+        assert_eq!(
+            refs(r#"{% import "macros.html" as m %}"#),
+            names(&["macros.html"])
+        );
+        assert_eq!(
+            refs(r#"{% from "macros.html" import link as l %}"#),
+            names(&["macros.html"])
+        );
+        assert_eq!(
+            refs(r#"{% include "a.html" if wide else "b.html" %}"#),
+            names(&["a.html", "b.html"])
+        );
+        assert_eq!(
+            refs(r#"{% include "partials/" ~ "nav.html" %}"#),
+            names(&["partials/nav.html"])
+        );
+        // Quotes in other tags and text are not template names.
+        assert_eq!(
+            refs(r#"{% set x = "a.html" %}{{ "b.html" }} include "c.html""#),
+            names(&[])
+        );
+    }
+
+    #[test]
+    fn test_nested_referenced_templates() {
+        let source = r#"
+            {% block content %}
+              {% for page in pages %}{% include "card.html" %}{% else %}{% include "empty.html" %}{% endfor %}
+              {% if x %}{% include "a.html" %}{% elif y %}{% include "b.html" %}{% endif %}
+              {% macro m() %}{% include "macro.html" %}{% endmacro %}
+              {% call m() %}{% include "call.html" %}{% endcall %}
+              {% filter upper %}{% with a = 1 %}{% include "with.html" %}{% endwith %}{% endfilter %}
+            {% endblock %}
+        "#;
+
+        assert_eq!(
+            refs(source),
+            names(&[
+                "card.html",
+                "empty.html",
+                "a.html",
+                "b.html",
+                "macro.html",
+                "call.html",
+                "with.html"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_dynamic_referenced_templates() {
+        assert_eq!(refs("{% include page.template %}"), (vec![], true));
+        assert_eq!(refs(r#"{% extends "layouts/" ~ layout %}"#), (vec![], true));
+        assert_eq!(
+            refs(r#"{% import theme ~ "/macros.html" as m %}"#),
+            (vec![], true)
+        );
+        assert_eq!(refs(r#"{% include ["a.html", name] %}"#), (vec![], true));
+        assert_eq!(
+            refs(r#"{% include "a.html" if wide else name %}"#),
+            (vec!["a.html".to_string()], true)
+        );
+    }
+}
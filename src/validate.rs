@@ -0,0 +1,261 @@
+use serde::{
+    Deserialize, Deserializer,
+    de::{Error as _, Visitor, value::Error},
+    forward_to_deserialize_any,
+};
+
+use crate::config::{Build, Config, Language, MenuEntry, Serve, Site, SiteOverride};
+
+/// Minimum similarity for a known key to be suggested in place of an unknown one.
+const SUGGESTION_THRESHOLD: f64 = 0.8;
+
+/// Deserializer which records the field names of the struct requested from it.
+struct FieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldsDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        *self.0 = fields;
+        Err(Error::custom("fields recorded"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Returns the keys accepted when deserializing the struct `T`.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields
+}
+
+fn suggest(key: &str, known: &[&str]) -> Option<String> {
+    known
+        .iter()
+        .map(|k| (strsim::jaro_winkler(key, k), k))
+        .filter(|(score, _)| *score >= SUGGESTION_THRESHOLD)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, k)| k.to_string())
+}
+
+/// Report keys in `table` which aren't in `known`, prefixed with `path`.
+fn check_keys(path: &str, table: &toml::Table, known: &[&str], warnings: &mut Vec<String>) {
+    for key in table.keys() {
+        if known.contains(&key.as_str()) {
+            continue;
+        }
+
+        let full_key = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+
+        let mut warning = format!("unknown configuration key '{full_key}'");
+        if let Some(suggestion) = suggest(key, known) {
+            warning.push_str(&format!(", did you mean '{suggestion}'?"));
+        }
+
+        warnings.push(warning);
+    }
+}
+
+fn check_section<'de, T: Deserialize<'de>>(
+    path: &str,
+    value: Option<&toml::Value>,
+    warnings: &mut Vec<String>,
+) {
+    if let Some(table) = value.and_then(|v| v.as_table()) {
+        check_keys(path, table, struct_fields::<T>(), warnings);
+    }
+}
+
+fn check_menu_entries(path: &str, value: &toml::Value, warnings: &mut Vec<String>) {
+    for entry in value.as_array().into_iter().flatten() {
+        check_section::<MenuEntry>(path, Some(entry), warnings);
+
+        if let Some(children) = entry.get("children") {
+            check_menu_entries(&format!("{path}.children"), children, warnings);
+        }
+    }
+}
+
+/// Returns warnings for keys in a configuration table which would be ignored.
+///
+/// Free-form tables, such as `[extra]` and plugin arguments, are not checked.
+pub fn unknown_keys(table: &toml::Table) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    // `env` tables are consumed before the configuration is deserialized.
+    let mut known = struct_fields::<Config>().to_vec();
+    known.push("env");
+    check_keys("", table, &known, &mut warnings);
+
+    check_section::<Site>("site", table.get("site"), &mut warnings);
+    check_section::<Build>("build", table.get("build"), &mut warnings);
+    check_section::<Serve>("serve", table.get("serve"), &mut warnings);
+
+    if let Some(menus) = table.get("menu").and_then(|m| m.as_table()) {
+        for (name, entries) in menus {
+            check_menu_entries(&format!("menu.{name}"), entries, &mut warnings);
+        }
+    }
+
+    for lang in table
+        .get("language")
+        .and_then(|l| l.as_array())
+        .into_iter()
+        .flatten()
+    {
+        check_section::<Language>("language", Some(lang), &mut warnings);
+        check_section::<SiteOverride>("language.site", lang.get("site"), &mut warnings);
+    }
+
+    warnings
+}
+
+/// Returns warnings for configured directories which don't exist.
+///
+/// The content directory is always checked. Other directories are optional,
+/// so are only checked if set in the configuration table.
+pub fn missing_dirs(config: &Config, table: &toml::Table) -> Vec<String> {
+    let build = &config.build;
+    let is_set = |key: &str| table.get("build").and_then(|b| b.get(key)).is_some();
+
+    let dirs = [
+        ("content_dir", &build.content_dir, true),
+        ("template_dir", &build.template_dir, is_set("template_dir")),
+        ("static_dir", &build.static_dir, is_set("static_dir")),
+        ("data_dir", &build.data_dir, is_set("data_dir")),
+        ("i18n_dir", &build.i18n_dir, is_set("i18n_dir")),
+        (
+            "archetype_dir",
+            &build.archetype_dir,
+            is_set("archetype_dir"),
+        ),
+        (
+            "plugin_dir",
+            &build.plugin_dir,
+            is_set("plugin_dir") || !config.plugins.is_empty(),
+        ),
+    ];
+
+    dirs.into_iter()
+        .filter(|(_, dir, check)| *check && !config.project_path(dir).is_dir())
+        .map(|(key, dir, _)| {
+            format!(
+                "directory '{}' for build.{} does not exist",
+                config.project_path(dir).display(),
+                key
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LoadOptions;
+    use std::fs;
+
+    fn unknown(config: &str) -> Vec<String> {
+        unknown_keys(&toml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let warnings = unknown(
+            r#"
+themes = "plain"
+
+[site]
+title = "Blog"
+base_ulr = "https://example.com"
+
+[build]
+content = "posts"
+
+[extra]
+anything = true
+
+[env.prod.site]
+base_url = "https://example.com"
+
+[[menu.main]]
+name = "Docs"
+url = "/docs/"
+children = [{ name = "Setup", url = "/docs/setup/", wieght = 1 }]
+
+[[language]]
+code = "de"
+site = { titl = "Blog" }
+
+[[plugin]]
+hook = "pre"
+name = "plugin.sh"
+hash = ""
+option = "value"
+"#,
+        );
+
+        assert_eq!(
+            warnings,
+            [
+                "unknown configuration key 'themes', did you mean 'theme'?",
+                "unknown configuration key 'site.base_ulr', did you mean 'base_url'?",
+                "unknown configuration key 'build.content', did you mean 'content_dir'?",
+                "unknown configuration key 'menu.main.children.wieght', did you mean 'weight'?",
+                "unknown configuration key 'language.site.titl', did you mean 'title'?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_without_suggestion() {
+        assert_eq!(
+            unknown("[serve]\nzzz = 1\n"),
+            ["unknown configuration key 'serve.zzz'"]
+        );
+        assert!(unknown("[site]\ntitle = \"Blog\"\n").is_empty());
+    }
+
+    #[test]
+    fn test_missing_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tars.toml");
+        let source = "[build]\nstatic_dir = \"assets\"\n\n[[plugin]]\nhook = \"pre\"\nname = \"a.sh\"\nhash = \"\"\n";
+        fs::write(&path, source).unwrap();
+        fs::create_dir(dir.path().join("assets")).unwrap();
+
+        let config = Config::from_file(&path.to_string_lossy(), &LoadOptions::default()).unwrap();
+        let warnings = missing_dirs(&config, &toml::from_str(source).unwrap());
+
+        let missing = |name: &str, key: &str| {
+            format!(
+                "directory '{}' for build.{key} does not exist",
+                dir.path().join(name).display()
+            )
+        };
+        assert_eq!(
+            warnings,
+            [
+                missing("content", "content_dir"),
+                missing("plugin", "plugin_dir")
+            ]
+        );
+    }
+}